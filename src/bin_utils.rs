@@ -1,11 +1,85 @@
 use std::error::Error;
+use std::fs::{self, File};
 use std::io::BufReader;
-use std::io::Write;
-use std::process::Command;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
+use nix::sys::signal::Signal;
+
+use crate::cgroup::Cgroup;
+pub use crate::cgroup::CgroupConfig;
 pub use crate::checker::*;
+pub use crate::language::*;
+use crate::problem::{CheckerConfig, IoMode};
 use crate::process::*;
-pub use crate::utils::*;
+use crate::sandbox::{sandbox, Supervisor};
+pub use crate::sandbox::{BindMount, SandboxConfig};
+pub use crate::scratch::ScratchConfig;
+use crate::scratch::WorkDir;
+use crate::streams::*;
+use crate::utils::*;
+
+/// Maximum amount of stderr kept from each run of the program, in bytes
+pub const STDERR_LIMIT: usize = 16 * 1024;
+
+/// How the judged program is run
+#[derive(Debug, Clone, Default)]
+pub struct RunConfig {
+    /// Run the program in its own cgroup v2 created with this configuration,
+    /// failing to spawn it if the cgroup can't be created
+    pub cgroup: Option<CgroupConfig>,
+    /// Isolate the program in a sandbox, failing to spawn it if the sandbox
+    /// can't be set up
+    pub sandbox: Option<SandboxConfig>,
+    /// Language profiles for compiling submissions, on top of the built-in
+    /// ones
+    pub languages: Vec<LanguageProfile>,
+    /// Where the program reads the input and writes the output
+    pub io: IoMode,
+    /// Working directory of each run
+    pub scratch: ScratchConfig,
+    /// Environment of the program, empty unless configured
+    pub env: EnvPolicy,
+}
+
+/// Spawn the judged program, in its own cgroup if configured and in
+/// `work_dir` if given
+pub(crate) fn spawn(
+    command: &mut Command,
+    memory_limit: u64,
+    config: &RunConfig,
+    work_dir: Option<&Path>,
+) -> std::io::Result<(Child, Option<Cgroup>, Option<Supervisor>)> {
+    let cgroup = config
+        .cgroup
+        .as_ref()
+        .map(|cgroup_config| Cgroup::create(cgroup_config, memory_limit))
+        .transpose()?;
+
+    // Joining the cgroup has to happen before entering the sandbox
+    if let Some(cgroup) = &cgroup {
+        cgroup.attach(command);
+    }
+
+    let mut supervisor = match (&config.sandbox, work_dir) {
+        (Some(sandbox_config), _) => Some(sandbox(command, sandbox_config, work_dir)?),
+        (None, Some(work_dir)) => {
+            command.current_dir(work_dir);
+            None
+        }
+        (None, None) => None,
+    };
+
+    let child = command.spawn()?;
+    if let Some(supervisor) = &mut supervisor {
+        supervisor.find_program();
+    }
+
+    Ok((child, cgroup, supervisor))
+}
 
 /// Judge a problem against a single test case
 ///
@@ -14,8 +88,8 @@ pub use crate::utils::*;
 /// `command`: Command to execute
 /// `command_option`: Command options
 /// `input_path`: Path to the input file
-/// `time_limit`: Time limit in ms
-/// `memory_limit`: Memory limit in Mb
+/// `limits`: CPU time, wall time, memory and output limits
+/// `config`: How to run the program
 /// `checker`: The checker that checks for correctness
 ///
 /// # Returns
@@ -25,45 +99,597 @@ pub fn judge(
     cmd: &str,
     command_options: Option<Vec<String>>,
     input_path: &str,
-    time_limit: u64,
-    memory_limit: u64,
+    limits: Limits,
+    config: &RunConfig,
     checker: impl Checker,
 ) -> Result<ProblemVerdict, Box<dyn Error>> {
-    let input = std::fs::read_to_string(input_path)?;
+    judge_with_usage(cmd, command_options, input_path, limits, config, checker)
+        .map(|(verdict, _, _)| verdict)
+}
+
+/// Same as `judge`, also returning the resources used and the stderr of the
+/// program whatever the verdict
+pub fn judge_with_usage(
+    cmd: &str,
+    command_options: Option<Vec<String>>,
+    input_path: &str,
+    limits: Limits,
+    config: &RunConfig,
+    checker: impl Checker,
+) -> Result<(ProblemVerdict, Usage, Option<String>), Box<dyn Error>> {
+    let mut work_dir = WorkDir::new(&config.scratch)?;
 
-    let mut child = Command::new(cmd)
+    // With file I/O the input is the only file in the working directory, stdin
+    // is empty and stdout is ignored
+    let mut input_path = input_path;
+    if let IoMode::Files { input, .. } = &config.io {
+        fs::copy(input_path, work_dir.path().join(input))?;
+        input_path = "/dev/null";
+    }
+
+    let mut command = Command::new(program_path(cmd, config)?);
+    set_env(&mut command, &config.env);
+    command
         .args(command_options.unwrap_or(vec![]))
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped())
-        .spawn()?;
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if config.io != IoMode::Stdio {
+        limit_file_size(&mut command, limits.output);
+    }
+
+    let (mut child, cgroup, supervisor) =
+        spawn(&mut command, limits.memory, config, Some(work_dir.path()))?;
 
     let pid = Pid::from_raw(child.id() as i32);
-    let mut process = Process::new(pid, time_limit, memory_limit);
+    let mut process = Process::new(pid, limits.time, limits.wall_time, limits.memory);
+    process.cgroup = cgroup;
+    process.supervisor = supervisor;
 
-    if let Some(stdin) = child.stdin.as_mut() {
-        stdin.write_all(input.as_bytes())?;
-    }
+    // Feed and drain the pipes while the process runs so it never blocks on a
+    // full pipe buffer
+    let spool_path = temp_file_path("out");
+    let stderr = capture(child.stderr.take().unwrap(), STDERR_LIMIT + 1);
+    let feeder = feed(child.stdin.take().unwrap(), input_path);
+    // Once the program is reaped its pid may belong to something else, without
+    // a handle closing the pipe still stops it on its next write
+    let pidfd = PidFd::open(pid).ok();
+    let spooler = spool(
+        child.stdout.take().unwrap(),
+        &spool_path,
+        limits.output.saturating_mul(1024 * 1024),
+        move || {
+            if let Some(pidfd) = pidfd {
+                let _ = pidfd.kill();
+            }
+        },
+    );
+
+    let (feeder, spooler) = match (feeder, spooler) {
+        (Ok(feeder), Ok(spooler)) => (feeder, spooler),
+        (feeder, spooler) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            let _ = std::fs::remove_file(&spool_path);
+            return Err(feeder.err().or(spooler.err()).unwrap().into());
+        }
+    };
 
     wait_for(&mut process);
 
-    match process.state {
-        ProcessState::Exited(0) => {
-            let stdout = child.stdout.as_mut().unwrap();
-            let mut reader = BufReader::new(stdout);
-
-            match checker.check(&mut reader) {
-                Ok(()) => Ok(ProblemVerdict::Accepted {
-                    time: process.consumed_time_ms,
-                    memory: process.consumed_memory_mb,
-                }),
-                Err(msg) => Ok(ProblemVerdict::WrongAnswer { msg }),
+    // Kill whatever is left of the program so nothing holds the pipes open
+    drop(process.cgroup.take());
+
+    let fed = feeder.join().unwrap();
+    let spooled = spooler.join().unwrap();
+    let stderr = stderr_text(stderr.join().unwrap());
+
+    let output_file = match &config.io {
+        IoMode::Files { output, .. } => Some(work_dir.path().join(output)),
+        IoMode::Stdio => None,
+    };
+    let res = check_spooled(
+        &process,
+        config,
+        fed.and(spooled),
+        &spool_path,
+        output_file.as_deref(),
+        &stderr,
+        checker,
+    );
+    let _ = std::fs::remove_file(&spool_path);
+    keep_if_failed(&mut work_dir, config, &res);
+
+    let usage = Usage {
+        cpu_time: process.consumed_time_ms,
+        wall_time: process.consumed_wall_time_ms,
+        memory: process.consumed_memory_mb,
+    };
+
+    res.map(|verdict| (verdict, usage, stderr))
+}
+
+/// Path of the program to run in its working directory, relative paths would
+/// be resolved from there while in the sandbox they are relative to its root
+fn program_path(cmd: &str, config: &RunConfig) -> std::io::Result<PathBuf> {
+    match config.sandbox {
+        None if cmd.contains('/') => fs::canonicalize(cmd),
+        _ => Ok(PathBuf::from(cmd)),
+    }
+}
+
+/// Give the program only the environment allowed by the policy
+fn set_env(command: &mut Command, policy: &EnvPolicy) {
+    command.env_clear();
+
+    for name in &policy.pass {
+        if let Some(value) = std::env::var_os(name) {
+            command.env(name, value);
+        }
+    }
+    command.envs(&policy.set);
+}
+
+/// Keep the working directory of a run that wasn't accepted if configured
+fn keep_if_failed(
+    work_dir: &mut WorkDir,
+    config: &RunConfig,
+    res: &Result<ProblemVerdict, Box<dyn Error>>,
+) {
+    if config.scratch.keep_failed && !matches!(res, Ok(ProblemVerdict::Accepted { .. })) {
+        eprintln!(
+            "Kept the working directory of the failed run in {}",
+            work_dir.keep().display()
+        );
+    }
+}
+
+/// Make writing files past `limit` Mb kill the program with `SIGXFSZ`, 0 means
+/// unlimited
+fn limit_file_size(command: &mut Command, limit: u64) {
+    if limit == 0 {
+        return;
+    }
+
+    let bytes = limit.saturating_mul(1024 * 1024);
+    unsafe {
+        command.pre_exec(move || {
+            let rlimit = libc::rlimit {
+                rlim_cur: bytes,
+                rlim_max: bytes,
+            };
+            if libc::setrlimit(libc::RLIMIT_FSIZE, &rlimit) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            Ok(())
+        });
+    }
+}
+
+/// Captured stderr as text, `None` if the program wrote nothing
+fn stderr_text(captured: std::io::Result<Vec<u8>>) -> Option<String> {
+    let mut captured = captured.ok().filter(|captured| !captured.is_empty())?;
+
+    let truncated = captured.len() > STDERR_LIMIT;
+    captured.truncate(STDERR_LIMIT);
+    let mut text = String::from_utf8_lossy(&captured).into_owned();
+    if truncated {
+        text.push_str("\n[truncated]");
+    }
+
+    Some(text)
+}
+
+/// Judge a problem against the test cases `{test_case}.in` and
+/// `{test_case}.out` of a directory
+///
+/// # Arguments
+///
+/// `command`: Command to execute
+/// `command_option`: Command options
+/// `directory`: Directory with the test cases
+/// `test_cases`: Test cases to judge, in order
+/// `limits`: CPU time, wall time, memory and output limits
+/// `config`: How to run the program
+/// `run_all`: Judge every test case instead of stopping at the first failure
+///
+/// # Returns
+///
+/// The report with the result of each judged test case
+pub fn judge_directory(
+    cmd: &str,
+    command_options: Option<Vec<String>>,
+    directory: &str,
+    test_cases: impl IntoIterator<Item = u32>,
+    limits: Limits,
+    config: &RunConfig,
+    run_all: bool,
+) -> Result<TestReport, Box<dyn Error>> {
+    judge_directory_with_checker(
+        cmd,
+        command_options,
+        directory,
+        test_cases,
+        limits,
+        config,
+        &CheckerConfig::Lines,
+        run_all,
+        |_| {},
+    )
+}
+
+/// Same as `judge_directory`, checking the outputs with the given checker and
+/// calling `on_result` with the result of each test case as soon as it's
+/// judged
+#[allow(clippy::too_many_arguments)]
+pub fn judge_directory_with_checker(
+    cmd: &str,
+    command_options: Option<Vec<String>>,
+    directory: &str,
+    test_cases: impl IntoIterator<Item = u32>,
+    limits: Limits,
+    config: &RunConfig,
+    checker: &CheckerConfig,
+    run_all: bool,
+    mut on_result: impl FnMut(&TestResult),
+) -> Result<TestReport, Box<dyn Error>> {
+    let mut tests = Vec::new();
+
+    for test_case in test_cases {
+        let input = format!("{}/{}.in", directory, test_case);
+        let output = format!("{}/{}.out", directory, test_case);
+
+        let args = command_options.clone();
+        let (verdict, usage, stderr) = match checker {
+            CheckerConfig::Lines => {
+                let checker = LinesChecker::new(&output);
+                judge_with_usage(cmd, args, &input, limits, config, checker)?
+            }
+            CheckerConfig::Tokens { abs_eps, rel_eps } => {
+                let checker = TokensChecker::new(&output, *abs_eps, *rel_eps);
+                judge_with_usage(cmd, args, &input, limits, config, checker)?
+            }
+            CheckerConfig::External { path, time, memory } => {
+                let path = Path::new(directory).join(path);
+                let checker =
+                    ExternalChecker::new(&path.to_string_lossy(), &input, &output, *time, *memory);
+                judge_with_usage(cmd, args, &input, limits, config, checker)?
             }
+        };
+        let accepted = matches!(verdict, ProblemVerdict::Accepted { .. });
+
+        let result = TestResult {
+            test_case,
+            verdict,
+            usage,
+            stderr,
+        };
+        on_result(&result);
+        tests.push(result);
+
+        if !accepted && !run_all {
+            break;
         }
-        ProcessState::Exited(_) => Ok(ProblemVerdict::RuntimeError(0)),
-        ProcessState::TimeLimitExceeded => Ok(ProblemVerdict::TimeLimitExceeded),
-        ProcessState::MemoryLimitExceeded => Ok(ProblemVerdict::MemoryLimitExceeded),
-        ProcessState::IdleLimitExceeded => Ok(ProblemVerdict::IdleLimitExceeded),
-        ProcessState::RuntimeError(sig) => Ok(ProblemVerdict::RuntimeError(sig)),
+    }
+
+    Ok(TestReport::new(tests))
+}
+
+/// Verdict of a finished process that didn't exit successfully, `None` if it
+/// did
+fn failure_verdict(
+    process: &Process,
+    config: &RunConfig,
+    stderr: &Option<String>,
+) -> Result<Option<ProblemVerdict>, Box<dyn Error>> {
+    let runtime_error = |termination| ProblemVerdict::RuntimeError {
+        termination,
+        stderr: stderr.clone(),
+    };
+
+    match process.state {
+        ProcessState::Exited(0) => Ok(None),
+        ProcessState::Exited(code) => Ok(Some(runtime_error(Termination::ExitCode(code)))),
+        ProcessState::TimeLimitExceeded => Ok(Some(ProblemVerdict::TimeLimitExceeded)),
+        ProcessState::WallTimeLimitExceeded => Ok(Some(ProblemVerdict::WallTimeLimitExceeded)),
+        ProcessState::MemoryLimitExceeded => Ok(Some(ProblemVerdict::MemoryLimitExceeded)),
+        ProcessState::IdleLimitExceeded => Ok(Some(ProblemVerdict::IdleLimitExceeded)),
+        // The seccomp filter kills with SIGSYS on forbidden syscalls
+        ProcessState::RuntimeError(libc::SIGSYS)
+            if config
+                .sandbox
+                .as_ref()
+                .is_some_and(|sandbox| sandbox.filter_syscalls) =>
+        {
+            Ok(Some(ProblemVerdict::SecurityViolation))
+        }
+        // Writing past the file size limit set for file I/O
+        ProcessState::RuntimeError(libc::SIGXFSZ) if config.io != IoMode::Stdio => {
+            Ok(Some(ProblemVerdict::OutputLimitExceeded))
+        }
+        ProcessState::RuntimeError(sig) => Ok(Some(runtime_error(signal_termination(sig)))),
         _ => Err("An unexpected error ocurred".into()),
     }
 }
+
+/// Termination by the signal `number`, with its name and what it usually means
+fn signal_termination(number: i32) -> Termination {
+    let Ok(signal) = Signal::try_from(number) else {
+        return Termination::Signal {
+            number,
+            name: format!("signal {}", number),
+            description: String::from("Unknown signal"),
+        };
+    };
+
+    let description = match signal {
+        Signal::SIGSEGV => "Segmentation fault, invalid memory access",
+        Signal::SIGFPE => "Floating point exception, e.g. division by zero",
+        Signal::SIGABRT => "Aborted, e.g. a failed assertion",
+        Signal::SIGBUS => "Bus error, misaligned or unmapped memory access",
+        Signal::SIGILL => "Illegal instruction",
+        Signal::SIGKILL => "Killed",
+        Signal::SIGTERM => "Terminated",
+        Signal::SIGPIPE => "Broken pipe, wrote to a closed pipe",
+        Signal::SIGXCPU => "CPU time limit exceeded",
+        Signal::SIGXFSZ => "File size limit exceeded",
+        Signal::SIGSYS => "Bad system call",
+        Signal::SIGTRAP => "Trace or breakpoint trap",
+        Signal::SIGHUP => "Hangup",
+        Signal::SIGINT => "Interrupted",
+        Signal::SIGQUIT => "Quit",
+        Signal::SIGALRM => "Alarm clock",
+        Signal::SIGSTOP | Signal::SIGTSTP | Signal::SIGTTIN | Signal::SIGTTOU => "Stopped",
+        _ => "Unexpected signal",
+    };
+
+    Termination::Signal {
+        number,
+        name: String::from(signal.as_str()),
+        description: String::from(description),
+    }
+}
+
+/// Get the verdict of a finished process whose output was spooled to a file,
+/// or written to `output_file` with file I/O
+fn check_spooled(
+    process: &Process,
+    config: &RunConfig,
+    spooled: std::io::Result<Option<u64>>,
+    spool_path: &str,
+    output_file: Option<&Path>,
+    stderr: &Option<String>,
+    checker: impl Checker,
+) -> Result<ProblemVerdict, Box<dyn Error>> {
+    if let Ok(None) = spooled {
+        return Ok(ProblemVerdict::OutputLimitExceeded);
+    }
+
+    if let Some(verdict) = failure_verdict(process, config, stderr)? {
+        return Ok(verdict);
+    }
+
+    spooled?;
+    let output = match output_file {
+        Some(path) => match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                return Ok(ProblemVerdict::PresentationError {
+                    msg: format!("Output file {} not found", name),
+                });
+            }
+            Err(err) => return Err(err.into()),
+        },
+        None => File::open(spool_path)?,
+    };
+    let mut reader = BufReader::new(output);
+
+    match checker.check(&mut reader) {
+        Ok(()) => Ok(ProblemVerdict::Accepted {
+            cpu_time: process.consumed_time_ms,
+            wall_time: process.consumed_wall_time_ms,
+            memory: process.consumed_memory_mb,
+        }),
+        Err(CheckerError::WrongAnswer(msg)) => Ok(ProblemVerdict::WrongAnswer { msg }),
+        Err(CheckerError::PresentationError(msg)) => Ok(ProblemVerdict::PresentationError { msg }),
+        Err(CheckerError::PartialScore { points, msg }) => {
+            Ok(ProblemVerdict::PartiallyCorrect { points, msg })
+        }
+        Err(CheckerError::Failed(msg)) => Err(format!("Checker failed: {}", msg).into()),
+    }
+}
+
+/// Judge an interactive problem against a single test case
+///
+/// The program and the interactor are run at the same time with the stdout of
+/// each one connected to the stdin of the other. The interactor gets the test
+/// input path and decides the verdict with its exit code.
+///
+/// # Arguments
+///
+/// `command`: Command to execute
+/// `command_option`: Command options
+/// `input_path`: Path to the input file, only read by the interactor
+/// `limits`: CPU time, wall time and memory limits, the output goes to the interactor so the
+/// output limit doesn't apply
+/// `config`: How to run the program, the interactor is always run directly
+/// `interactor`: The interactor that talks with the program
+///
+/// # Returns
+///
+/// The verdict of the judge
+pub fn judge_interactive(
+    cmd: &str,
+    command_options: Option<Vec<String>>,
+    input_path: &str,
+    limits: Limits,
+    config: &RunConfig,
+    interactor: &Interactor,
+) -> Result<ProblemVerdict, Box<dyn Error>> {
+    let mut work_dir = WorkDir::new(&config.scratch)?;
+    let program = program_path(cmd, config)?;
+    let output_path = temp_file_path("out");
+
+    let mut interactor_child = Command::new(&interactor.interactor_path)
+        .args([input_path, &output_path])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let to_program = interactor_child.stdout.take().unwrap();
+    let from_program = interactor_child.stdin.take().unwrap();
+    let interactor_stderr = capture(interactor_child.stderr.take().unwrap(), MESSAGE_LIMIT);
+
+    let interactor_pid = Pid::from_raw(interactor_child.id() as i32);
+    let mut interactor_process = Process::new(
+        interactor_pid,
+        interactor.time_limit_ms,
+        default_wall_time(interactor.time_limit_ms),
+        interactor.memory_limit_mb,
+    );
+
+    let mut command = Command::new(program);
+    set_env(&mut command, &config.env);
+    let child = spawn(
+        command
+            .args(command_options.unwrap_or(vec![]))
+            .stdin(Stdio::from(to_program))
+            .stdout(Stdio::from(from_program))
+            .stderr(Stdio::piped()),
+        limits.memory,
+        config,
+        Some(work_dir.path()),
+    );
+    // The command holds the other ends of the pipes, the interactor wouldn't
+    // read EOF once the program exits
+    drop(command);
+
+    let (mut child, cgroup, mut supervisor) = match child {
+        Ok(child) => child,
+        Err(err) => {
+            let _ = interactor_child.kill();
+            let _ = interactor_child.wait();
+            return Err(err.into());
+        }
+    };
+
+    let pid = Pid::from_raw(child.id() as i32);
+    // A sandboxed program exits before the process forwarding its status
+    let pidfds = PidFd::open(interactor_pid).and_then(|interactor| {
+        let program = match supervisor.as_mut().and_then(Supervisor::take_program_pidfd) {
+            Some(program) => program,
+            None => PidFd::open(pid)?,
+        };
+        Ok((interactor, program))
+    });
+    let (interactor_pidfd, program_pidfd) = match pidfds {
+        Ok(pidfds) => pidfds,
+        Err(err) => {
+            for child in [&mut child, &mut interactor_child] {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+            return Err(err.into());
+        }
+    };
+
+    let mut process = Process::new(pid, limits.time, limits.wall_time, limits.memory);
+    process.cgroup = cgroup;
+    process.supervisor = supervisor;
+    let stderr = capture(child.stderr.take().unwrap(), STDERR_LIMIT + 1);
+
+    // Order in which both sides exit, taken as soon as each one does since
+    // `wait_for` only notices it on its next poll
+    let exits = AtomicUsize::new(0);
+    let exit_order = |pidfd: &PidFd| {
+        let _ = pidfd.wait_exit();
+        exits.fetch_add(1, Ordering::SeqCst)
+    };
+
+    let (program_exit, interactor_exit) = thread::scope(|scope| {
+        let program_exit = scope.spawn(|| exit_order(&program_pidfd));
+        let interactor_exit = scope.spawn(|| exit_order(&interactor_pidfd));
+        let program_thread = scope.spawn(|| wait_for(&mut process));
+
+        wait_for(&mut interactor_process);
+        program_thread.join().unwrap();
+
+        (
+            program_exit.join().unwrap(),
+            interactor_exit.join().unwrap(),
+        )
+    });
+
+    drop(process.cgroup.take());
+    let stderr = stderr_text(stderr.join().unwrap());
+
+    let msg = message(interactor_stderr.join().unwrap());
+    let _ = std::fs::remove_file(&output_path);
+
+    // Whoever finished first is to blame, the other side most likely just
+    // found a closed pipe
+    let program_verdict = failure_verdict(&process, config, &stderr)?;
+    let program_first = program_exit < interactor_exit;
+    let interactor_out_of_time = matches!(
+        interactor_process.state,
+        ProcessState::TimeLimitExceeded
+            | ProcessState::WallTimeLimitExceeded
+            | ProcessState::IdleLimitExceeded
+    );
+
+    let res = match (program_verdict, program_first) {
+        (Some(verdict), true) => Ok(verdict),
+        // An interactor running out of time before the program exits was
+        // left waiting by it, like in a deadlock
+        (program_verdict, false) if interactor_out_of_time => Ok(match program_verdict {
+            Some(
+                verdict @ (ProblemVerdict::TimeLimitExceeded
+                | ProblemVerdict::WallTimeLimitExceeded
+                | ProblemVerdict::IdleLimitExceeded),
+            ) => verdict,
+            _ => ProblemVerdict::IdleLimitExceeded,
+        }),
+        (program_verdict, _) => {
+            interaction_verdict(&process, &interactor_process, msg, program_verdict)
+        }
+    };
+    keep_if_failed(&mut work_dir, config, &res);
+
+    res
+}
+
+/// Verdict of an interaction in which the program didn't fail first
+fn interaction_verdict(
+    process: &Process,
+    interactor_process: &Process,
+    msg: String,
+    program_verdict: Option<ProblemVerdict>,
+) -> Result<ProblemVerdict, Box<dyn Error>> {
+    let interactor_result = match &interactor_process.state {
+        ProcessState::Exited(code) => testlib_result(*code, msg),
+        state => Err(CheckerError::Failed(format!(
+            "Interactor finished unexpectedly with {:?}",
+            state
+        ))),
+    };
+
+    match (interactor_result, program_verdict) {
+        (Ok(()), None) => Ok(ProblemVerdict::Accepted {
+            cpu_time: process.consumed_time_ms,
+            wall_time: process.consumed_wall_time_ms,
+            memory: process.consumed_memory_mb,
+        }),
+        (Ok(()), Some(verdict)) => Ok(verdict),
+        (Err(CheckerError::WrongAnswer(msg)), _) => Ok(ProblemVerdict::WrongAnswer { msg }),
+        (Err(CheckerError::PresentationError(msg)), _) => {
+            Ok(ProblemVerdict::PresentationError { msg })
+        }
+        (Err(CheckerError::PartialScore { points, msg }), _) => {
+            Ok(ProblemVerdict::PartiallyCorrect { points, msg })
+        }
+        (Err(CheckerError::Failed(msg)), _) => Err(format!("Interactor failed: {}", msg).into()),
+    }
+}
@@ -0,0 +1,178 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::sleep;
+use std::time::Duration;
+
+static CGROUP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Where and how to create the cgroups of the runs
+#[derive(Debug, Clone)]
+pub struct CgroupConfig {
+    /// A cgroup v2 directory the judge can create cgroups in, e.g.
+    /// `/sys/fs/cgroup/codechecker`. It shouldn't contain processes itself so
+    /// the controllers can be enabled for its children.
+    pub parent: PathBuf,
+    /// Maximum amount of processes and threads, 0 means no limit
+    pub max_pids: u64,
+}
+
+/// A cgroup v2 holding a single run, the processes in it are killed and the
+/// cgroup removed when dropped
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Create a cgroup for a single run with the given memory limit in Mb (0
+    /// means no limit)
+    pub fn create(config: &CgroupConfig, memory_limit_mb: u64) -> io::Result<Self> {
+        if !config.parent.join("cgroup.controllers").exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Not a cgroup v2 directory",
+            ));
+        }
+
+        // This fails if the controllers are already enabled, which is fine
+        let _ = fs::write(
+            config.parent.join("cgroup.subtree_control"),
+            "+memory +pids +cpu",
+        );
+
+        let count = CGROUP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = config
+            .parent
+            .join(format!("codechecker-{}-{}", std::process::id(), count));
+        fs::create_dir(&path)?;
+
+        let cgroup = Self { path };
+
+        // The current usage between polls would under-report the peak
+        if !cgroup.path.join("memory.peak").exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "No memory.peak in the cgroup, it needs Linux 5.19 and the memory controller",
+            ));
+        }
+
+        if memory_limit_mb > 0 {
+            let bytes = memory_limit_mb.saturating_mul(1024 * 1024);
+            cgroup.write("memory.max", &bytes.to_string())?;
+            // Missing without swap accounting
+            let _ = cgroup.write("memory.swap.max", "0");
+        }
+
+        if config.max_pids > 0 {
+            cgroup.write("pids.max", &config.max_pids.to_string())?;
+        }
+
+        Ok(cgroup)
+    }
+
+    /// Make the process spawned by `command` join the cgroup before exec, so
+    /// everything it runs is accounted for
+    pub fn attach(&self, command: &mut Command) {
+        let procs_path = CString::new(self.path.join("cgroup.procs").as_os_str().as_bytes())
+            .expect("Cgroup path shouldn't contain null bytes");
+
+        // Only async-signal-safe calls are allowed between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                let fd = libc::open(procs_path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                // "0" stands for the writing process
+                let written = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+                let err = io::Error::last_os_error();
+                libc::close(fd);
+
+                if written != 1 {
+                    return Err(err);
+                }
+
+                Ok(())
+            });
+        }
+    }
+
+    /// CPU time in ms used by all the processes of the cgroup
+    pub fn cpu_time_ms(&self) -> io::Result<u64> {
+        let stat = self.read("cpu.stat")?;
+        let usage_usec = read_key(&stat, "usage_usec").unwrap_or(0);
+
+        Ok(usage_usec / 1000)
+    }
+
+    /// Peak memory usage in Mb of all the processes of the cgroup
+    pub fn memory_peak_mb(&self) -> io::Result<u64> {
+        let bytes = self.read("memory.peak")?;
+        let bytes: u64 = bytes.trim().parse().unwrap_or(0);
+
+        Ok(bytes / 1024 / 1024)
+    }
+
+    /// Whether the kernel killed a process of the cgroup for going over the
+    /// memory limit
+    pub fn oom_killed(&self) -> bool {
+        self.read("memory.events")
+            .map(|events| read_key(&events, "oom_kill").unwrap_or(0) > 0)
+            .unwrap_or(false)
+    }
+
+    /// Kill every process in the cgroup
+    pub fn kill(&self) {
+        // cgroup.kill is only available since Linux 5.14
+        if self.write("cgroup.kill", "1").is_ok() {
+            return;
+        }
+
+        if let Ok(procs) = self.read("cgroup.procs") {
+            for pid in procs.lines().filter_map(|pid| pid.parse::<i32>().ok()) {
+                unsafe { libc::kill(pid, libc::SIGKILL) };
+            }
+        }
+    }
+
+    fn read(&self, file: &str) -> io::Result<String> {
+        fs::read_to_string(self.path.join(file))
+    }
+
+    fn write(&self, file: &str, value: &str) -> io::Result<()> {
+        fs::write(self.path.join(file), value)
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        self.kill();
+
+        // Killed processes take a moment to leave the cgroup
+        for _ in 0..50 {
+            if fs::remove_dir(&self.path).is_ok() {
+                return;
+            }
+            sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Read the value of a key from a flat keyed file like `cpu.stat`
+fn read_key(content: &str, key: &str) -> Option<u64> {
+    content.lines().find_map(|line| {
+        let (name, value) = line.split_once(' ')?;
+        if name == key {
+            value.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
@@ -1,8 +1,27 @@
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::process::*;
+use crate::streams::capture;
+use crate::utils::default_wall_time;
+
+/// Reasons for a checker to reject an output
+#[derive(Debug)]
+pub enum CheckerError {
+    WrongAnswer(String),
+    PresentationError(String),
+    PartialScore {
+        points: f64,
+        msg: String,
+    },
+    /// The checker itself failed, this is not the contestant's fault
+    Failed(String),
+}
 
 pub trait Checker {
-    fn check(&self, out_reader: &mut impl BufRead) -> Result<(), String>;
+    fn check(&self, out_reader: &mut impl BufRead) -> Result<(), CheckerError>;
 }
 
 pub struct LinesChecker {
@@ -18,7 +37,7 @@ impl LinesChecker {
 }
 
 impl Checker for LinesChecker {
-    fn check(&self, out_reader: &mut impl BufRead) -> Result<(), String> {
+    fn check(&self, out_reader: &mut impl BufRead) -> Result<(), CheckerError> {
         let answer_file = File::open(&self.answer_path).unwrap();
         let mut answer_reader = BufReader::new(answer_file);
 
@@ -38,15 +57,24 @@ impl Checker for LinesChecker {
                 .expect("Error reading from stdout of the program");
 
             if ans_buf.trim() != out_buf.trim() {
-                return Err(format!("Wrong answer in line {}", line));
+                return Err(CheckerError::WrongAnswer(format!(
+                    "Wrong answer in line {}",
+                    line
+                )));
             }
 
             if ans_res == 0 && out_res != 0 {
-                return Err(format!("Wrong answer in line {}", line));
+                return Err(CheckerError::WrongAnswer(format!(
+                    "Wrong answer in line {}",
+                    line
+                )));
             }
 
             if ans_res != 0 && out_res == 0 {
-                return Err(format!("Wrong answer in line {}", line));
+                return Err(CheckerError::WrongAnswer(format!(
+                    "Wrong answer in line {}",
+                    line
+                )));
             }
 
             if ans_res == 0 && out_res == 0 {
@@ -55,3 +83,237 @@ impl Checker for LinesChecker {
         }
     }
 }
+
+/// Compares the output token by token, splitting on whitespace. Tokens that
+/// parse as floating-point numbers in both the answer and the output are
+/// compared with the given absolute and relative tolerance, the rest must be
+/// equal.
+pub struct TokensChecker {
+    answer_path: String,
+    abs_eps: f64,
+    rel_eps: f64,
+}
+
+impl TokensChecker {
+    pub fn new(answer_path: &str, abs_eps: f64, rel_eps: f64) -> Self {
+        Self {
+            answer_path: String::from(answer_path),
+            abs_eps,
+            rel_eps,
+        }
+    }
+
+    fn tokens_match(&self, ans: &str, out: &str) -> bool {
+        if ans == out {
+            return true;
+        }
+
+        match (ans.parse::<f64>(), out.parse::<f64>()) {
+            (Ok(a), Ok(o)) if a.is_finite() && o.is_finite() => {
+                let diff = (a - o).abs();
+                diff <= self.abs_eps || diff <= self.rel_eps * a.abs()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Checker for TokensChecker {
+    fn check(&self, out_reader: &mut impl BufRead) -> Result<(), CheckerError> {
+        let answer = std::fs::read_to_string(&self.answer_path).unwrap();
+        let mut output = String::new();
+        out_reader
+            .read_to_string(&mut output)
+            .expect("Error reading from stdout of the program");
+
+        let mut ans_tokens = answer.split_whitespace();
+        let mut out_tokens = output.split_whitespace();
+
+        let mut token = 0;
+        loop {
+            token += 1;
+            match (ans_tokens.next(), out_tokens.next()) {
+                (Some(ans), Some(out)) => {
+                    if !self.tokens_match(ans, out) {
+                        return Err(CheckerError::WrongAnswer(format!(
+                            "Wrong answer in token {}: expected {:?}, found {:?}",
+                            token, ans, out
+                        )));
+                    }
+                }
+                (Some(ans), None) => {
+                    return Err(CheckerError::WrongAnswer(format!(
+                        "Wrong answer in token {}: expected {:?}, found end of output",
+                        token, ans
+                    )));
+                }
+                (None, Some(out)) => {
+                    return Err(CheckerError::WrongAnswer(format!(
+                        "Wrong answer in token {}: expected end of output, found {:?}",
+                        token, out
+                    )));
+                }
+                (None, None) => return Ok(()),
+            }
+        }
+    }
+}
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Path for a temporary file that is unique for this process
+pub(crate) fn temp_file_path(extension: &str) -> String {
+    let count = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let file_name = format!("codechecker-{}-{}.{}", std::process::id(), count, extension);
+
+    std::env::temp_dir()
+        .join(file_name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Map the exit code and message of a testlib-style program (checker or
+/// interactor) into the checking result
+///
+/// Exit codes are 0 (OK), 1 (WA), 2 (PE), 3 (FAIL) and 7 (points), for the
+/// latter the message is expected to start with the amount of points.
+pub(crate) fn testlib_result(exit_code: i32, msg: String) -> Result<(), CheckerError> {
+    match exit_code {
+        0 => Ok(()),
+        1 => Err(CheckerError::WrongAnswer(msg)),
+        2 => Err(CheckerError::PresentationError(msg)),
+        3 => Err(CheckerError::Failed(msg)),
+        7 => {
+            let msg = msg.strip_prefix("points").unwrap_or(&msg).trim_start();
+            let (points, rest) = msg.split_once(char::is_whitespace).unwrap_or((msg, ""));
+
+            match points.parse::<f64>() {
+                Ok(points) => Err(CheckerError::PartialScore {
+                    points,
+                    msg: String::from(rest.trim()),
+                }),
+                Err(_) => Err(CheckerError::Failed(format!(
+                    "Couldn't parse points from checker message {:?}",
+                    msg
+                ))),
+            }
+        }
+        code => Err(CheckerError::Failed(format!(
+            "Unexpected exit code {}: {}",
+            code, msg
+        ))),
+    }
+}
+
+/// Maximum amount of the message of a checker or interactor kept, in bytes
+pub(crate) const MESSAGE_LIMIT: usize = 64 * 1024;
+
+/// Message a checker or interactor wrote to its stderr
+pub(crate) fn message(captured: io::Result<Vec<u8>>) -> String {
+    let captured = captured.unwrap_or_default();
+
+    String::from(String::from_utf8_lossy(&captured).trim())
+}
+
+/// Testlib-compatible special judge, invoked as
+/// `checker <input> <output> <answer>`
+pub struct ExternalChecker {
+    checker_path: String,
+    input_path: String,
+    answer_path: String,
+    time_limit_ms: u64,
+    memory_limit_mb: u64,
+}
+
+impl ExternalChecker {
+    pub fn new(
+        checker_path: &str,
+        input_path: &str,
+        answer_path: &str,
+        time_limit_ms: u64,
+        memory_limit_mb: u64,
+    ) -> Self {
+        Self {
+            checker_path: String::from(checker_path),
+            input_path: String::from(input_path),
+            answer_path: String::from(answer_path),
+            time_limit_ms,
+            memory_limit_mb,
+        }
+    }
+
+    fn run(&self, output_path: &str) -> Result<(), CheckerError> {
+        let mut child = Command::new(&self.checker_path)
+            .args([&self.input_path, output_path, &self.answer_path])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| CheckerError::Failed(format!("Couldn't run checker: {}", err)))?;
+
+        let pid = Pid::from_raw(child.id() as i32);
+        let mut process = Process::new(
+            pid,
+            self.time_limit_ms,
+            default_wall_time(self.time_limit_ms),
+            self.memory_limit_mb,
+        );
+
+        // Drained while it runs so a long message can't fill the pipe and block
+        // the checker
+        let stderr = capture(child.stderr.take().unwrap(), MESSAGE_LIMIT);
+
+        wait_for(&mut process);
+
+        let msg = message(stderr.join().unwrap());
+
+        match process.state {
+            ProcessState::Exited(code) => testlib_result(code, msg),
+            state => Err(CheckerError::Failed(format!(
+                "Checker finished unexpectedly with {:?}",
+                state
+            ))),
+        }
+    }
+}
+
+impl Checker for ExternalChecker {
+    fn check(&self, out_reader: &mut impl BufRead) -> Result<(), CheckerError> {
+        let output_path = temp_file_path("out");
+
+        let mut output_file = File::create(&output_path)
+            .map_err(|err| CheckerError::Failed(format!("Couldn't create output file: {}", err)))?;
+        let copied = std::io::copy(out_reader, &mut output_file);
+        drop(output_file);
+
+        let res = match copied {
+            Ok(_) => self.run(&output_path),
+            Err(err) => Err(CheckerError::Failed(format!(
+                "Couldn't write output file: {}",
+                err
+            ))),
+        };
+
+        let _ = std::fs::remove_file(&output_path);
+
+        res
+    }
+}
+
+/// Testlib-compatible interactor, invoked as `interactor <input> <output>`
+/// with its stdin and stdout wired to the stdout and stdin of the program
+pub struct Interactor {
+    pub(crate) interactor_path: String,
+    pub(crate) time_limit_ms: u64,
+    pub(crate) memory_limit_mb: u64,
+}
+
+impl Interactor {
+    pub fn new(interactor_path: &str, time_limit_ms: u64, memory_limit_mb: u64) -> Self {
+        Self {
+            interactor_path: String::from(interactor_path),
+            time_limit_ms,
+            memory_limit_mb,
+        }
+    }
+}
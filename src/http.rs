@@ -0,0 +1,273 @@
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::ipc::{JudeRequest, PROTOCOL_VERSION};
+use crate::jobs::{Job, JobQueue};
+use crate::server::{ConnectionLimit, ServerConfig, CLIENT_TIMEOUT};
+
+/// Maximum size in bytes of the request line and each header
+const MAX_HEADER_SIZE: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+/// Maximum size in bytes of a request body, below the limit of the socket
+/// protocol since each connection holds its body in memory
+const MAX_BODY_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Time between keep-alive comments of an idle event stream
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+/// Serve the HTTP API forever, judging the submissions on `workers` threads.
+///
+/// - `POST /submissions` queues a `JudeRequest` and answers with its id
+/// - `GET /submissions/{id}` gives the state of the submission
+/// - `GET /submissions/{id}/tests` gives the result of each test case once
+///   judged
+/// - `GET /submissions/{id}/events` streams the progress as Server-Sent
+///   Events, a `test_case` event before running each test case and a
+///   `finished` event with the final state
+/// - `GET /problems` lists the problems of the catalog with their limits
+pub fn serve_http(listener: TcpListener, queue: &JobQueue, workers: usize, config: &ServerConfig) {
+    let limit = ConnectionLimit::new();
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| queue.work(config));
+        }
+
+        loop {
+            let slot = limit.acquire();
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    eprintln!("Couldn't get client {e}");
+                    continue;
+                }
+            };
+
+            scope.spawn(move || {
+                let _slot = slot;
+                if let Err(err) = handle_http(stream, queue, config) {
+                    eprintln!("Error handling HTTP request: {err}");
+                }
+            });
+        }
+    });
+}
+
+fn handle_http(
+    mut stream: TcpStream,
+    queue: &JobQueue,
+    config: &ServerConfig,
+) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let request = match read_request(&mut BufReader::new(&stream)) {
+        Ok(Some(request)) => request,
+        Ok(None) => return Ok(()),
+        Err(err) => {
+            respond_error(&mut stream, 400, "Invalid HTTP request")?;
+            return Err(err.into());
+        }
+    };
+
+    let path = request.path.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match (request.method.as_str(), &segments[..]) {
+        ("POST", ["submissions"]) => {
+            let submission: JudeRequest = match serde_json::from_slice(&request.body) {
+                Ok(submission) => submission,
+                Err(_) => return respond_error(&mut stream, 400, "Invalid judge request"),
+            };
+            if submission.version != PROTOCOL_VERSION {
+                return respond_error(&mut stream, 400, "Unsupported protocol version");
+            }
+
+            let id = queue.submit(submission);
+            respond(&mut stream, 202, &json!({ "id": id }))
+        }
+        ("GET", ["submissions", id]) => match find_job(queue, id) {
+            Some(job) => respond(&mut stream, 200, &job),
+            None => respond_error(&mut stream, 404, "Unknown submission"),
+        },
+        ("GET", ["submissions", id, "tests"]) => match find_job(queue, id) {
+            Some(Job {
+                report: Some(report),
+                ..
+            }) => respond(&mut stream, 200, &report.tests),
+            Some(_) => respond_error(&mut stream, 409, "Submission not judged yet"),
+            None => respond_error(&mut stream, 404, "Unknown submission"),
+        },
+        ("GET", ["submissions", id, "events"]) => match find_job(queue, id) {
+            Some(job) => stream_events(&mut stream, queue, job),
+            None => respond_error(&mut stream, 404, "Unknown submission"),
+        },
+        ("GET", ["problems"]) => match &config.catalog {
+            Some(catalog) => respond(&mut stream, 200, &catalog.list()),
+            None => respond_error(&mut stream, 404, "The judge has no problem catalog"),
+        },
+        (_, ["submissions"])
+        | (_, ["submissions", _])
+        | (_, ["submissions", _, _])
+        | (_, ["problems"]) => respond_error(&mut stream, 405, "Method not allowed"),
+        _ => respond_error(&mut stream, 404, "Not found"),
+    }
+}
+
+fn find_job(queue: &JobQueue, id: &str) -> Option<Job> {
+    queue.get(id.parse().ok()?)
+}
+
+/// Read a request, `None` if the client closed the connection without sending
+/// anything
+fn read_request(reader: &mut impl BufRead) -> io::Result<Option<HttpRequest>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    if reader.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+
+    let request_line = read_header_line(reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(invalid("Invalid request line"));
+    };
+
+    let mut content_length = 0;
+    for _ in 0..MAX_HEADERS {
+        let line = read_header_line(reader)?;
+        if line.is_empty() {
+            if content_length > MAX_BODY_SIZE {
+                return Err(invalid("Request body is too large"));
+            }
+
+            // Only what the client actually sends is allocated
+            let mut body = Vec::new();
+            reader.take(content_length).read_to_end(&mut body)?;
+            if body.len() as u64 != content_length {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Truncated request body",
+                ));
+            }
+
+            return Ok(Some(HttpRequest {
+                method: String::from(method),
+                path: String::from(path),
+                body,
+            }));
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("Invalid Content-Length"))?;
+            }
+        }
+    }
+
+    Err(invalid("Too many headers"))
+}
+
+/// Read a line of the head of the request, without the line break
+fn read_header_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    reader.take(MAX_HEADER_SIZE).read_line(&mut line)?;
+
+    if !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Truncated request head",
+        ));
+    }
+
+    Ok(String::from(line.trim_end()))
+}
+
+fn respond<T: ?Sized + Serialize>(
+    stream: &mut TcpStream,
+    status: u16,
+    body: &T,
+) -> Result<(), Box<dyn Error>> {
+    let body = serde_json::to_vec(body)?;
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason(status),
+        body.len()
+    );
+
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&body)?;
+
+    Ok(())
+}
+
+fn respond_error(stream: &mut TcpStream, status: u16, msg: &str) -> Result<(), Box<dyn Error>> {
+    respond(stream, status, &json!({ "error": msg }))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Unknown",
+    }
+}
+
+/// Send the progress of a job as Server-Sent Events until it's done
+fn stream_events(
+    stream: &mut TcpStream,
+    queue: &JobQueue,
+    mut job: Job,
+) -> Result<(), Box<dyn Error>> {
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+    )?;
+
+    let mut sent = 0;
+    loop {
+        for test_case in &job.test_cases[sent..] {
+            write!(stream, "event: test_case\ndata: {}\n\n", test_case)?;
+        }
+        let progressed = sent < job.test_cases.len();
+        sent = job.test_cases.len();
+
+        if job.is_done() {
+            write!(
+                stream,
+                "event: finished\ndata: {}\n\n",
+                serde_json::to_string(&job)?
+            )?;
+            return Ok(());
+        }
+
+        // Also finds out when the client went away
+        if !progressed {
+            stream.write_all(b": keep-alive\n\n")?;
+        }
+
+        job = match queue.wait_update(&job, KEEP_ALIVE_INTERVAL) {
+            Some(job) => job,
+            None => return Ok(()),
+        };
+    }
+}
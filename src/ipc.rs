@@ -1,51 +1,562 @@
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
 
-use crate::utils::ProblemVerdict;
+use crate::problem::{ProblemInfo, ScoreReport};
+use crate::utils::{default_wall_time, Limits, ProblemVerdict, TestReport, DEFAULT_OUTPUT_LIMIT};
+
+/// Version of the judge protocol, both sides must agree on it
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Maximum size in bytes of a single message
+pub const MAX_MESSAGE_SIZE: u64 = 64 * 1024 * 1024;
 
 #[skip_serializing_none]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JudeRequest {
-    pub cmd: String,
+    /// Always `PROTOCOL_VERSION`
+    pub version: u32,
+    /// Program to judge, unless `source` is given
+    pub cmd: Option<String>,
     pub cmd_args: Option<Vec<String>>,
-    pub time: u64,
-    pub memory: u64,
-    pub test_dir: String,
+    /// Source code to compile and judge instead of `cmd`
+    pub source: Option<String>,
+    /// Language profile of `source`
+    pub language: Option<String>,
+    /// Executable to judge encoded in base64, instead of `cmd`
+    pub binary: Option<String>,
+    /// CPU time limit in ms, required unless the problem descriptor has
+    /// limits. Ignored for problems of a catalog, which have their own.
+    pub time: Option<u64>,
+    /// Wall time limit in ms, `default_wall_time(time)` if not given
+    pub wall_time: Option<u64>,
+    /// Memory limit in Mb, required along with `time`
+    pub memory: Option<u64>,
+    /// Output limit in Mb, `DEFAULT_OUTPUT_LIMIT` if not given
+    pub output: Option<u64>,
+    /// Directory with the test cases, unless `problem` is given
+    pub test_dir: Option<String>,
+    /// Id of a problem in the problem root of the judge, instead of
+    /// `test_dir`
+    pub problem: Option<String>,
+    /// Judge every test case instead of stopping at the first failure
+    pub run_all: Option<bool>,
+    /// Variables set in the environment of the program, the ones of the
+    /// judge can't be passed through from a request
+    pub env: Option<BTreeMap<String, String>>,
+}
+
+impl JudeRequest {
+    /// Limits of each run of the requested judgment, `None` if the time or
+    /// memory limit is missing
+    pub fn limits(&self) -> Option<Limits> {
+        let (time, memory) = (self.time?, self.memory?);
+
+        Some(Limits {
+            time,
+            wall_time: self.wall_time.unwrap_or(default_wall_time(time)),
+            memory,
+            output: self.output.unwrap_or(DEFAULT_OUTPUT_LIMIT),
+        })
+    }
+}
+
+/// Follow the progress of a job submitted before, possibly from another
+/// connection
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JobQuery {
+    /// Always `PROTOCOL_VERSION`
+    pub version: u32,
+    pub job: u64,
+}
+
+/// What a `ListQuery` asks for
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListTarget {
+    /// Problems of the catalog of the judge, with their limits
+    Problems,
+}
+
+/// Ask the judge for a list, e.g. `{"version": 1, "list": "problems"}`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListQuery {
+    /// Always `PROTOCOL_VERSION`
+    pub version: u32,
+    pub list: ListTarget,
+}
+
+/// Anything a client can send to the judge
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ClientMessage {
+    Query(JobQuery),
+    List(ListQuery),
+    Judge(Box<JudeRequest>),
+}
+
+impl ClientMessage {
+    pub fn version(&self) -> u32 {
+        match self {
+            ClientMessage::Query(query) => query.version,
+            ClientMessage::List(query) => query.version,
+            ClientMessage::Judge(request) => request.version,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JudgeResponse {
+    pub version: u32,
     pub ok: bool,
     pub error: Option<String>,
     pub verdict: Option<ProblemVerdict>,
+    /// Id of the job judging the request, sent before anything else
+    pub job: Option<u64>,
     pub test_case: Option<u32>,
+    pub report: Option<TestReport>,
+    /// Per-group scores when the test directory has a problem descriptor
+    pub score: Option<ScoreReport>,
+    /// Answer to a list of the problems
+    pub problems: Option<Vec<ProblemInfo>>,
 }
 
 impl JudgeResponse {
     pub fn error(err: &str) -> Self {
         Self {
+            version: PROTOCOL_VERSION,
             ok: false,
             error: Some(String::from(err)),
             verdict: None,
+            job: None,
+            test_case: None,
+            report: None,
+            score: None,
+            problems: None,
+        }
+    }
+
+    pub fn job(job: u64) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            ok: true,
+            error: None,
+            verdict: None,
+            job: Some(job),
             test_case: None,
+            report: None,
+            score: None,
+            problems: None,
         }
     }
 
     pub fn test_case(test_case: u32) -> Self {
         Self {
+            version: PROTOCOL_VERSION,
             ok: true,
             error: None,
             verdict: None,
+            job: None,
             test_case: Some(test_case),
+            report: None,
+            score: None,
+            problems: None,
         }
     }
 
     pub fn ok(verdict: ProblemVerdict) -> Self {
         Self {
+            version: PROTOCOL_VERSION,
             ok: true,
             error: None,
             verdict: Some(verdict),
+            job: None,
+            test_case: None,
+            report: None,
+            score: None,
+            problems: None,
+        }
+    }
+
+    pub fn report(report: TestReport, score: Option<ScoreReport>) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            ok: true,
+            error: None,
+            verdict: Some(report.verdict.clone()),
+            job: None,
+            test_case: None,
+            report: Some(report),
+            score,
+            problems: None,
+        }
+    }
+
+    pub fn problems(problems: Vec<ProblemInfo>) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            ok: true,
+            error: None,
+            verdict: None,
+            job: None,
             test_case: None,
+            report: None,
+            score: None,
+            problems: Some(problems),
+        }
+    }
+}
+
+/// Write a message as a single line of JSON, messages are delimited by
+/// newlines in both directions
+pub fn write_message<T: Serialize>(writer: &mut impl Write, message: &T) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+
+    writer.write_all(&line)?;
+    writer.flush()
+}
+
+/// Read the next message, `None` if the other side closed the connection
+pub fn read_message<T: DeserializeOwned>(reader: &mut impl BufRead) -> io::Result<Option<T>> {
+    let mut line = String::new();
+    reader.take(MAX_MESSAGE_SIZE).read_line(&mut line)?;
+
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') && line.len() as u64 == MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Message is too large",
+        ));
+    }
+
+    Ok(Some(serde_json::from_str(&line)?))
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    /// The judge didn't answer in time, the connection shouldn't be used
+    /// anymore
+    Timeout,
+    /// The judge sent something that doesn't follow the protocol
+    Protocol(String),
+    /// The judge couldn't carry out the request
+    Judge(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(err) => write!(f, "Connection error: {}", err),
+            ClientError::Timeout => write!(f, "Timed out waiting for the judge"),
+            ClientError::Protocol(msg) => write!(f, "Protocol error: {}", msg),
+            ClientError::Judge(msg) => write!(f, "Judge error: {}", msg),
+        }
+    }
+}
+
+impl Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ClientError::Timeout,
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+                ClientError::Protocol(err.to_string())
+            }
+            _ => ClientError::Io(err),
+        }
+    }
+}
+
+/// Final result of a judgment
+#[derive(Debug)]
+pub struct Judgment {
+    pub verdict: ProblemVerdict,
+    pub report: Option<TestReport>,
+    pub score: Option<ScoreReport>,
+}
+
+#[derive(Debug)]
+pub enum JudgeEvent {
+    /// The judge queued the request as this job, which can be followed from
+    /// another connection with `JudgeClient::follow`
+    Job(u64),
+    /// The judge started running a test case
+    TestCase(u32),
+    Finished(Box<Judgment>),
+}
+
+/// Stream carrying the judge protocol, over TCP or a Unix domain socket
+#[derive(Debug)]
+pub enum Connection {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Connection {
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Connection::Tcp(stream) => stream.try_clone().map(Connection::Tcp),
+            Connection::Unix(stream) => stream.try_clone().map(Connection::Unix),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_read_timeout(timeout),
+            Connection::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_write_timeout(timeout),
+            Connection::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    /// Description of the other side for logging
+    pub fn peer(&self) -> String {
+        match self {
+            Connection::Tcp(stream) => stream
+                .peer_addr()
+                .map_or(String::from("unknown client"), |addr| addr.to_string()),
+            // Clients of Unix domain sockets are usually unnamed
+            Connection::Unix(_) => String::from("local client"),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Connection to a judge started with `codechecker listen`, which can carry
+/// several requests one after the other
+pub struct JudgeClient {
+    writer: Connection,
+    reader: BufReader<Connection>,
+}
+
+impl JudgeClient {
+    /// Connect to a judge, giving up after `timeout` if given
+    pub fn connect(
+        addr: impl ToSocketAddrs,
+        timeout: Option<Duration>,
+    ) -> Result<Self, ClientError> {
+        let mut last_err = io::Error::new(io::ErrorKind::InvalidInput, "No address to connect to");
+
+        for addr in addr.to_socket_addrs()? {
+            let stream = match timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+
+            match stream {
+                Ok(stream) => return Self::from_connection(Connection::Tcp(stream)),
+                Err(err) => last_err = err,
+            }
+        }
+
+        Err(last_err.into())
+    }
+
+    /// Connect to a judge listening in a Unix domain socket
+    pub fn connect_unix(path: impl AsRef<Path>) -> Result<Self, ClientError> {
+        let stream = UnixStream::connect(path)?;
+
+        Self::from_connection(Connection::Unix(stream))
+    }
+
+    fn from_connection(connection: Connection) -> Result<Self, ClientError> {
+        Ok(Self {
+            reader: BufReader::new(connection.try_clone()?),
+            writer: connection,
+        })
+    }
+
+    /// Maximum time to wait for each message of the judge, `None` waits
+    /// forever. Progress is reported before every test case, so this should
+    /// be above the wall time limit of a single run.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), ClientError> {
+        self.writer.set_read_timeout(timeout)?;
+        self.writer.set_write_timeout(timeout)?;
+
+        Ok(())
+    }
+
+    /// Send a request, its progress and result are read from the returned
+    /// iterator, which has to be consumed before submitting another request
+    pub fn submit(&mut self, mut request: JudeRequest) -> Result<JudgeEvents<'_>, ClientError> {
+        request.version = PROTOCOL_VERSION;
+        write_message(&mut self.writer, &request)?;
+
+        Ok(JudgeEvents {
+            client: self,
+            finished: false,
+        })
+    }
+
+    /// Follow a job submitted before, its whole progress is sent again from
+    /// the start
+    pub fn follow(&mut self, job: u64) -> Result<JudgeEvents<'_>, ClientError> {
+        let query = ClientMessage::Query(JobQuery {
+            version: PROTOCOL_VERSION,
+            job,
+        });
+        write_message(&mut self.writer, &query)?;
+
+        Ok(JudgeEvents {
+            client: self,
+            finished: false,
+        })
+    }
+
+    /// Problems of the catalog of the judge, with their limits
+    pub fn list_problems(&mut self) -> Result<Vec<ProblemInfo>, ClientError> {
+        let query = ClientMessage::List(ListQuery {
+            version: PROTOCOL_VERSION,
+            list: ListTarget::Problems,
+        });
+        write_message(&mut self.writer, &query)?;
+
+        let response: JudgeResponse = read_message(&mut self.reader)?.ok_or_else(|| {
+            ClientError::Protocol(String::from("Connection closed before the answer"))
+        })?;
+
+        if response.version != PROTOCOL_VERSION {
+            return Err(ClientError::Protocol(format!(
+                "Unsupported protocol version {}",
+                response.version
+            )));
+        }
+
+        match response {
+            JudgeResponse {
+                ok: false, error, ..
+            } => Err(ClientError::Judge(
+                error.unwrap_or_else(|| String::from("Unknown error")),
+            )),
+            JudgeResponse {
+                problems: Some(problems),
+                ..
+            } => Ok(problems),
+            _ => Err(ClientError::Protocol(String::from(
+                "Response without problems",
+            ))),
+        }
+    }
+
+    /// Send a request and wait for its result, calling `on_test_case` as the
+    /// judge goes through the test cases
+    pub fn judge(
+        &mut self,
+        request: JudeRequest,
+        mut on_test_case: impl FnMut(u32),
+    ) -> Result<Judgment, ClientError> {
+        for event in self.submit(request)? {
+            match event? {
+                JudgeEvent::Job(_) => {}
+                JudgeEvent::TestCase(test_case) => on_test_case(test_case),
+                JudgeEvent::Finished(judgment) => return Ok(*judgment),
+            }
+        }
+
+        Err(ClientError::Protocol(String::from(
+            "Connection closed before the verdict",
+        )))
+    }
+
+    fn next_event(&mut self) -> Result<JudgeEvent, ClientError> {
+        let response: JudgeResponse = read_message(&mut self.reader)?.ok_or_else(|| {
+            ClientError::Protocol(String::from("Connection closed before the verdict"))
+        })?;
+
+        if response.version != PROTOCOL_VERSION {
+            return Err(ClientError::Protocol(format!(
+                "Unsupported protocol version {}",
+                response.version
+            )));
+        }
+
+        match response {
+            JudgeResponse {
+                ok: false, error, ..
+            } => Err(ClientError::Judge(
+                error.unwrap_or_else(|| String::from("Unknown error")),
+            )),
+            JudgeResponse { job: Some(job), .. } => Ok(JudgeEvent::Job(job)),
+            JudgeResponse {
+                test_case: Some(test_case),
+                ..
+            } => Ok(JudgeEvent::TestCase(test_case)),
+            JudgeResponse {
+                verdict: Some(verdict),
+                report,
+                score,
+                ..
+            } => Ok(JudgeEvent::Finished(Box::new(Judgment {
+                verdict,
+                report,
+                score,
+            }))),
+            _ => Err(ClientError::Protocol(String::from(
+                "Response without job, test case nor verdict",
+            ))),
         }
     }
 }
+
+/// Progress of a submitted request, ending after the verdict or an error
+pub struct JudgeEvents<'a> {
+    client: &'a mut JudgeClient,
+    finished: bool,
+}
+
+impl Iterator for JudgeEvents<'_> {
+    type Item = Result<JudgeEvent, ClientError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let event = self.client.next_event();
+        self.finished = !matches!(event, Ok(JudgeEvent::Job(_) | JudgeEvent::TestCase(_)));
+
+        Some(event)
+    }
+}
@@ -0,0 +1,483 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ipc::JudeRequest;
+use crate::problem::ScoreReport;
+use crate::server::{judge_request, ServerConfig};
+use crate::utils::{ProblemVerdict, TestReport};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Finished,
+    Failed,
+}
+
+/// State of a submitted judgment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub status: JobStatus,
+    /// Test cases started so far, in order
+    pub test_cases: Vec<u32>,
+    pub error: Option<String>,
+    pub verdict: Option<ProblemVerdict>,
+    pub report: Option<TestReport>,
+    pub score: Option<ScoreReport>,
+}
+
+impl Job {
+    fn new(id: u64) -> Self {
+        Self {
+            id,
+            status: JobStatus::Queued,
+            test_cases: Vec::new(),
+            error: None,
+            verdict: None,
+            report: None,
+            score: None,
+        }
+    }
+
+    /// Whether the job won't change anymore
+    pub fn is_done(&self) -> bool {
+        matches!(self.status, JobStatus::Finished | JobStatus::Failed)
+    }
+}
+
+/// Done jobs kept by default, the oldest ones are forgotten past it
+pub const DEFAULT_MAX_DONE_JOBS: usize = 10000;
+
+/// Line of the job store. The request is kept in a file of its own until the
+/// job is done, so it can be judged again after a restart and the lines stay
+/// small however large the submission.
+#[derive(Serialize)]
+struct StoredJobRef<'a> {
+    job: &'a Job,
+    /// Relative to the directory of the store
+    request: Option<&'a Path>,
+}
+
+#[derive(Deserialize)]
+struct StoredJob {
+    job: Job,
+    request: Option<PathBuf>,
+}
+
+/// JSON-lines file where every submission and result is appended, rewritten
+/// once most of it is superseded. The requests are next to it in a directory
+/// named after it, e.g. `jobs.requests` for `jobs.jsonl`.
+struct Store {
+    path: PathBuf,
+    file: File,
+    /// Size of the last line of each job kept
+    lines: HashMap<u64, u64>,
+    /// Bytes of the lines still in use and of the superseded ones
+    live: u64,
+    stale: u64,
+}
+
+impl Store {
+    /// Write a new store at `path` with only the last state of each job, and
+    /// the requests of the ones not done, whose files must already exist.
+    /// The files of other requests are removed.
+    fn create(
+        path: &Path,
+        jobs: &BTreeMap<u64, Job>,
+        requests: &HashMap<u64, JudeRequest>,
+    ) -> io::Result<Self> {
+        let compacted = path.with_extension("tmp");
+        let mut file = File::create(&compacted)?;
+        let mut lines = HashMap::new();
+        for job in jobs.values() {
+            let request = requests
+                .contains_key(&job.id)
+                .then(|| request_path(path, job.id));
+            let len = write_stored(&mut file, job, request.as_deref())?;
+            lines.insert(job.id, len);
+        }
+        file.sync_all()?;
+        fs::rename(&compacted, path)?;
+
+        let store = Self {
+            path: path.to_path_buf(),
+            file: OpenOptions::new().append(true).open(path)?,
+            live: lines.values().sum(),
+            lines,
+            stale: 0,
+        };
+
+        // Left behind by a crash between writing a request and its job
+        let dir = store.dir().join(requests_dir(path));
+        if let Ok(entries) = fs::read_dir(&dir) {
+            for entry in entries.flatten() {
+                let name = PathBuf::from(entry.file_name());
+                let id = name.file_stem().and_then(|id| id.to_str()?.parse().ok());
+                if !id.is_some_and(|id| requests.contains_key(&id)) {
+                    let _ = fs::remove_file(entry.path());
+                }
+            }
+        }
+
+        Ok(store)
+    }
+
+    /// Append the state of the job, writing its request to its own file
+    /// before if given, or removing that file otherwise
+    fn write(&mut self, job: &Job, request: Option<&JudeRequest>) -> io::Result<()> {
+        let relative = request_path(&self.path, job.id);
+        let request_file = self.dir().join(&relative);
+
+        let stored_request = match request {
+            Some(request) => {
+                fs::create_dir_all(request_file.parent().unwrap())?;
+                fs::write(&request_file, serde_json::to_vec(request)?)?;
+                Some(relative.as_path())
+            }
+            None => None,
+        };
+
+        let len = write_stored(&mut self.file, job, stored_request)?;
+        self.live += len;
+        if let Some(old) = self.lines.insert(job.id, len) {
+            self.supersede(old);
+        }
+
+        // Only once no line of the job needs it anymore
+        if request.is_none() {
+            match fs::remove_file(&request_file) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn forget(&mut self, id: u64) {
+        if let Some(old) = self.lines.remove(&id) {
+            self.supersede(old);
+        }
+    }
+
+    fn supersede(&mut self, len: u64) {
+        self.live -= len;
+        self.stale += len;
+    }
+
+    /// Whether rewriting the store would at least halve it, so the rewrites
+    /// take time proportional to what's written
+    fn needs_compaction(&self) -> bool {
+        self.stale > self.live
+    }
+
+    /// Directory the paths of the requests are relative to
+    fn dir(&self) -> &Path {
+        store_dir(&self.path)
+    }
+}
+
+fn store_dir(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new("."))
+}
+
+fn requests_dir(path: &Path) -> PathBuf {
+    PathBuf::from(
+        path.with_extension("requests")
+            .file_name()
+            .unwrap_or_default(),
+    )
+}
+
+/// Path of the request of a job, relative to the directory of the store
+fn request_path(path: &Path, id: u64) -> PathBuf {
+    requests_dir(path).join(format!("{id}.json"))
+}
+
+struct QueueState {
+    jobs: BTreeMap<u64, Job>,
+    /// Requests of the jobs that aren't done, dropped once they are
+    requests: HashMap<u64, JudeRequest>,
+    pending: VecDeque<u64>,
+    /// Done jobs, from the oldest to the newest
+    done: VecDeque<u64>,
+    max_done: usize,
+    next_id: u64,
+    store: Option<Store>,
+}
+
+impl Default for QueueState {
+    fn default() -> Self {
+        Self {
+            jobs: BTreeMap::new(),
+            requests: HashMap::new(),
+            pending: VecDeque::new(),
+            done: VecDeque::new(),
+            max_done: DEFAULT_MAX_DONE_JOBS,
+            next_id: 0,
+            store: None,
+        }
+    }
+}
+
+impl QueueState {
+    fn persist(&mut self, id: u64) {
+        let (Some(store), Some(job)) = (self.store.as_mut(), self.jobs.get(&id)) else {
+            return;
+        };
+
+        // The judgment goes on even if it can't be stored
+        if let Err(err) = store.write(job, self.requests.get(&id)) {
+            eprintln!("Couldn't store job {id}: {err}");
+        }
+    }
+
+    /// Forget the oldest done jobs past the limit, rewriting the store if
+    /// most of it isn't needed anymore
+    fn trim(&mut self) {
+        while self.done.len() > self.max_done {
+            let id = self.done.pop_front().unwrap();
+            self.jobs.remove(&id);
+            if let Some(store) = self.store.as_mut() {
+                store.forget(id);
+            }
+        }
+
+        let Some(path) = self
+            .store
+            .as_ref()
+            .filter(|store| store.needs_compaction())
+            .map(|store| store.path.clone())
+        else {
+            return;
+        };
+        match Store::create(&path, &self.jobs, &self.requests) {
+            Ok(store) => self.store = Some(store),
+            Err(err) => eprintln!("Couldn't compact the job store: {err}"),
+        }
+    }
+}
+
+fn write_stored(writer: &mut impl Write, job: &Job, request: Option<&Path>) -> io::Result<u64> {
+    let mut line = serde_json::to_vec(&StoredJobRef { job, request })?;
+    line.push(b'\n');
+    writer.write_all(&line)?;
+
+    Ok(line.len() as u64)
+}
+
+fn read_request(path: &Path) -> Result<JudeRequest, Box<dyn Error>> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Judgments submitted to the workers, kept after they finish so their
+/// results can be fetched
+#[derive(Default)]
+pub struct JobQueue {
+    state: Mutex<QueueState>,
+    /// Notified on every change of a job and every submission
+    changed: Condvar,
+}
+
+impl JobQueue {
+    /// Queue only kept in memory
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue kept in a JSON-lines file, created if missing. The jobs that
+    /// weren't done when the file was last written are judged again.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut jobs = BTreeMap::new();
+        let mut request_files = HashMap::new();
+        let mut requests = HashMap::new();
+
+        if path.exists() {
+            for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                // A crash can leave the last line halfway written
+                match serde_json::from_str::<StoredJob>(&line) {
+                    Ok(StoredJob { job, request }) => {
+                        if let Some(request) = request {
+                            request_files.insert(job.id, request);
+                        }
+                        jobs.insert(job.id, job);
+                    }
+                    Err(err) => eprintln!("Skipping line {} of the job store: {err}", i + 1),
+                }
+            }
+        }
+
+        let mut pending = VecDeque::new();
+        for job in jobs.values_mut().filter(|job| !job.is_done()) {
+            let request = request_files
+                .get(&job.id)
+                .map(|file| read_request(&store_dir(path).join(file)));
+            if let Some(Err(err)) = &request {
+                eprintln!("Couldn't read the request of job {}: {err}", job.id);
+            }
+
+            if let Some(Ok(request)) = request {
+                requests.insert(job.id, request);
+                *job = Job::new(job.id);
+                pending.push_back(job.id);
+            } else {
+                job.status = JobStatus::Failed;
+                job.error = Some(String::from("Request lost"));
+            }
+        }
+
+        let mut state = QueueState {
+            next_id: jobs.keys().max().copied().unwrap_or(0),
+            done: jobs
+                .values()
+                .filter(|job| job.is_done())
+                .map(|job| job.id)
+                .collect(),
+            jobs,
+            requests,
+            pending,
+            ..QueueState::default()
+        };
+        state.trim();
+        state.store = Some(Store::create(path, &state.jobs, &state.requests)?);
+
+        Ok(Self {
+            state: Mutex::new(state),
+            changed: Condvar::new(),
+        })
+    }
+
+    /// Keep at most `max_done` done jobs, the oldest ones are forgotten
+    pub fn with_max_done(self, max_done: usize) -> Self {
+        {
+            let mut state = self.lock();
+            state.max_done = max_done;
+            state.trim();
+        }
+
+        self
+    }
+
+    /// Queue a request, returning the id of its job
+    pub fn submit(&self, request: JudeRequest) -> u64 {
+        let mut state = self.lock();
+        state.next_id += 1;
+        let id = state.next_id;
+
+        state.jobs.insert(id, Job::new(id));
+        state.requests.insert(id, request);
+        state.persist(id);
+        state.pending.push_back(id);
+        self.changed.notify_all();
+
+        id
+    }
+
+    pub fn get(&self, id: u64) -> Option<Job> {
+        self.lock().jobs.get(&id).cloned()
+    }
+
+    /// Wait until the job is different from `seen` or `timeout` passes,
+    /// returning its current state
+    pub fn wait_update(&self, seen: &Job, timeout: Duration) -> Option<Job> {
+        let state = self.lock();
+        let (state, _) = self
+            .changed
+            .wait_timeout_while(state, timeout, |state| {
+                state.jobs.get(&seen.id).is_some_and(|job| {
+                    job.status == seen.status && job.test_cases.len() == seen.test_cases.len()
+                })
+            })
+            .unwrap_or_else(|err| err.into_inner());
+
+        state.jobs.get(&seen.id).cloned()
+    }
+
+    /// Run the queued jobs one after the other, forever
+    pub fn work(&self, config: &ServerConfig) {
+        loop {
+            let (id, request) = {
+                let state = self.lock();
+                let mut state = self
+                    .changed
+                    .wait_while(state, |state| state.pending.is_empty())
+                    .unwrap_or_else(|err| err.into_inner());
+
+                let id = state.pending.pop_front().unwrap();
+                // The stored request stays until the job is done, in case the
+                // judge stops halfway
+                (id, state.requests[&id].clone())
+            };
+
+            self.update(id, |job| job.status = JobStatus::Running);
+
+            // A bug in a single judgment shouldn't take a worker down
+            let response = panic::catch_unwind(AssertUnwindSafe(|| {
+                judge_request(request, config, |test_case| {
+                    self.update(id, |job| job.test_cases.push(test_case));
+                })
+            }));
+
+            self.finish(id, |job| match response {
+                Ok(Ok(response)) => {
+                    job.status = JobStatus::Finished;
+                    job.verdict = response.verdict;
+                    job.report = response.report;
+                    job.score = response.score;
+                }
+                Ok(Err(err)) => {
+                    eprintln!("Error judging job {id}: {err}");
+                    job.status = JobStatus::Failed;
+                    job.error = Some(String::from(err.msg));
+                }
+                Err(_) => {
+                    eprintln!("Judging job {id} panicked");
+                    job.status = JobStatus::Failed;
+                    job.error = Some(String::from("Internal judge error"));
+                }
+            });
+        }
+    }
+
+    fn update(&self, id: u64, change: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.lock().jobs.get_mut(&id) {
+            change(job);
+        }
+        self.changed.notify_all();
+    }
+
+    /// Like `update` for the final state of the job, which is stored
+    fn finish(&self, id: u64, change: impl FnOnce(&mut Job)) {
+        let mut state = self.lock();
+        if let Some(job) = state.jobs.get_mut(&id) {
+            change(job);
+        }
+        state.requests.remove(&id);
+        state.persist(id);
+        state.done.push_back(id);
+        state.trim();
+        self.changed.notify_all();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        // Jobs are only changed by short critical sections that can't panic
+        // halfway, so the state is still consistent after a poisoning
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
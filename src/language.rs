@@ -0,0 +1,301 @@
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use nix::sys::signal::{killpg, Signal};
+use serde::{Deserialize, Serialize};
+
+use crate::bin_utils::{spawn, BindMount, RunConfig, SandboxConfig};
+use crate::checker::temp_file_path;
+use crate::process::*;
+use crate::streams::capture;
+
+/// Maximum amount of compiler output kept, in bytes
+pub const COMPILE_LOG_LIMIT: usize = 64 * 1024;
+
+fn default_compile_time() -> u64 {
+    10000
+}
+
+fn default_compile_memory() -> u64 {
+    2048
+}
+
+/// How to compile and run the programs of a language. In the commands
+/// `{source}` is replaced by the path of the source file, `{exe}` by the path
+/// of the compiled program and `{dir}` by the directory holding both.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LanguageProfile {
+    /// Name used to pick the language, e.g. `cpp`
+    pub name: String,
+    /// Name given to the source file, some languages expect a specific one
+    pub source_file: String,
+    /// Command compiling the source, missing for interpreted languages
+    pub compile: Option<Vec<String>>,
+    /// Command running the program
+    pub run: Vec<String>,
+    /// Time limit of the compilation in ms, measured in wall time since
+    /// compilers usually run several processes
+    #[serde(default = "default_compile_time")]
+    pub compile_time: u64,
+    /// Memory limit of the compilation in Mb
+    #[serde(default = "default_compile_memory")]
+    pub compile_memory: u64,
+}
+
+/// Command running a compiled program
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub cmd: String,
+    pub args: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum Compilation {
+    Success(Program),
+    /// The source didn't compile, with the output of the compiler
+    Error(String),
+}
+
+/// Directory holding the files of a submission while it's judged, like its
+/// source, compiled program or uploaded executable. It's removed with
+/// everything in it when dropped.
+#[derive(Debug)]
+pub struct BuildDir {
+    path: PathBuf,
+}
+
+impl BuildDir {
+    pub fn new() -> io::Result<Self> {
+        let path = PathBuf::from(temp_file_path("build"));
+        fs::create_dir(&path)?;
+
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write an uploaded executable into the directory, named `main`
+    pub fn write_executable(&self, bytes: &[u8]) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o755)
+            .open(self.path.join("main"))?;
+        file.write_all(bytes)?;
+
+        Ok(())
+    }
+
+    /// Make the directory visible to the programs run with `config`, mounted
+    /// read-only in their sandbox if any, returning where they see it
+    pub fn mount(&self, config: &mut RunConfig) -> PathBuf {
+        match &mut config.sandbox {
+            Some(sandbox) => {
+                sandbox.mounts.push(BindMount {
+                    source: self.path.clone(),
+                    target: sandbox.build_dir.clone(),
+                    writable: false,
+                });
+                sandbox.build_dir.clone()
+            }
+            None => self.path.clone(),
+        }
+    }
+}
+
+impl Drop for BuildDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn profile(
+    name: &str,
+    source_file: &str,
+    compile: Option<&[&str]>,
+    run: &[&str],
+) -> LanguageProfile {
+    let to_strings = |args: &[&str]| args.iter().map(|arg| String::from(*arg)).collect();
+
+    LanguageProfile {
+        name: String::from(name),
+        source_file: String::from(source_file),
+        compile: compile.map(to_strings),
+        run: to_strings(run),
+        compile_time: default_compile_time(),
+        compile_memory: default_compile_memory(),
+    }
+}
+
+/// Profiles available without any configuration
+pub fn builtin_languages() -> Vec<LanguageProfile> {
+    vec![
+        profile(
+            "cpp",
+            "main.cpp",
+            Some(&["g++", "-O2", "-std=c++17", "-o", "{exe}", "{source}"]),
+            &["{exe}"],
+        ),
+        profile(
+            "c",
+            "main.c",
+            Some(&["gcc", "-O2", "-std=c11", "-o", "{exe}", "{source}", "-lm"]),
+            &["{exe}"],
+        ),
+        profile(
+            "rust",
+            "main.rs",
+            Some(&[
+                "rustc",
+                "-O",
+                "--edition",
+                "2021",
+                "-o",
+                "{exe}",
+                "{source}",
+            ]),
+            &["{exe}"],
+        ),
+        profile(
+            "java",
+            "Main.java",
+            Some(&["javac", "-d", "{dir}", "{source}"]),
+            &["java", "-cp", "{dir}", "Main"],
+        ),
+        profile("python", "main.py", None, &["python3", "{source}"]),
+    ]
+}
+
+/// Load profiles from a JSON file holding a list of them
+pub fn load_languages(path: impl AsRef<Path>) -> Result<Vec<LanguageProfile>, Box<dyn Error>> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+impl LanguageProfile {
+    fn expand(&self, template: &[String], dir: &Path) -> Vec<String> {
+        let dir = dir.to_string_lossy();
+        let source = format!("{}/{}", dir, self.source_file);
+        let exe = format!("{}/main", dir);
+
+        template
+            .iter()
+            .map(|arg| {
+                arg.replace("{source}", &source)
+                    .replace("{exe}", &exe)
+                    .replace("{dir}", &dir)
+            })
+            .collect()
+    }
+
+    /// Write the source code into `dir` and compile it there, the compiler
+    /// runs in its own cgroup and in the sandbox if configured. In the
+    /// sandbox, without the syscall filter, `dir` is mounted writable as its
+    /// build directory, and the paths in the program are the ones inside the
+    /// sandbox (see `BuildDir::mount`).
+    pub fn compile(
+        &self,
+        source: &str,
+        dir: &Path,
+        config: &RunConfig,
+    ) -> Result<Compilation, Box<dyn Error>> {
+        fs::write(dir.join(&self.source_file), source)?;
+
+        // Where the compiler and the program see the directory
+        let seen_dir = match &config.sandbox {
+            Some(sandbox) => &sandbox.build_dir,
+            None => dir,
+        };
+
+        let mut run = self.expand(&self.run, seen_dir).into_iter();
+        let program = Program {
+            cmd: run.next().ok_or("Empty run command")?,
+            args: run.collect(),
+        };
+
+        let Some(compile) = &self.compile else {
+            return Ok(Compilation::Success(program));
+        };
+        let mut compile = self.expand(compile, seen_dir).into_iter();
+        let compiler = compile.next().ok_or("Empty compile command")?;
+
+        let compile_config = RunConfig {
+            cgroup: config.cgroup.clone(),
+            sandbox: config.sandbox.as_ref().map(|sandbox| SandboxConfig {
+                work_dir: sandbox.build_dir.clone(),
+                filter_syscalls: false,
+                ..sandbox.clone()
+            }),
+            ..RunConfig::default()
+        };
+        let (mut child, cgroup, supervisor) = spawn(
+            Command::new(compiler)
+                .args(compile)
+                // The root of the sandbox is read-only
+                .env("TMPDIR", seen_dir)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                // Its own process group, to kill what the compiler runs
+                .process_group(0),
+            self.compile_memory,
+            &compile_config,
+            Some(dir),
+        )?;
+
+        let stdout = capture(child.stdout.take().unwrap(), COMPILE_LOG_LIMIT);
+        let stderr = capture(child.stderr.take().unwrap(), COMPILE_LOG_LIMIT);
+
+        let pid = Pid::from_raw(child.id() as i32);
+        let mut process = Process::new(
+            pid,
+            self.compile_time,
+            self.compile_time,
+            self.compile_memory,
+        );
+        process.cgroup = cgroup;
+        process.supervisor = supervisor;
+
+        wait_for(&mut process);
+
+        let _ = killpg(pid, Signal::SIGKILL);
+        drop(process.cgroup.take());
+
+        let mut log = stdout.join().unwrap()?;
+        log.extend(stderr.join().unwrap()?);
+        log.truncate(COMPILE_LOG_LIMIT);
+        let mut log = String::from_utf8_lossy(&log).into_owned();
+
+        match process.state {
+            ProcessState::Exited(0) => Ok(Compilation::Success(program)),
+            ProcessState::TimeLimitExceeded | ProcessState::WallTimeLimitExceeded => {
+                log.push_str("\nCompilation time limit exceeded");
+                Ok(Compilation::Error(log))
+            }
+            ProcessState::MemoryLimitExceeded => {
+                log.push_str("\nCompilation memory limit exceeded");
+                Ok(Compilation::Error(log))
+            }
+            ProcessState::Failed => Err("Couldn't wait for the compiler".into()),
+            _ => Ok(Compilation::Error(log)),
+        }
+    }
+}
+
+impl RunConfig {
+    /// Profile of a language, the configured ones take precedence over the
+    /// built-in ones
+    pub fn language(&self, name: &str) -> Option<LanguageProfile> {
+        self.languages
+            .iter()
+            .cloned()
+            .chain(builtin_languages())
+            .find(|profile| profile.name == name)
+    }
+}
@@ -1,13 +1,44 @@
 #[cfg(feature = "binary")]
 mod bin_utils;
 #[cfg(feature = "binary")]
+mod cgroup;
+#[cfg(feature = "binary")]
 mod checker;
 #[cfg(feature = "binary")]
+mod http;
+#[cfg(feature = "binary")]
+mod jobs;
+#[cfg(feature = "binary")]
+mod language;
+#[cfg(feature = "binary")]
 mod process;
+#[cfg(feature = "binary")]
+mod sandbox;
+#[cfg(feature = "binary")]
+mod scratch;
+#[cfg(feature = "binary")]
+mod seccomp;
+#[cfg(feature = "binary")]
+mod server;
+#[cfg(feature = "binary")]
+mod streams;
 
 #[cfg(feature = "binary")]
 pub use bin_utils::*;
+#[cfg(feature = "binary")]
+pub use http::serve_http;
+#[cfg(feature = "binary")]
+pub use jobs::{Job, JobQueue, JobStatus, DEFAULT_MAX_DONE_JOBS};
+#[cfg(feature = "binary")]
+pub use server::*;
 
+#[cfg(feature = "ipc")]
 mod ipc;
+mod problem;
 mod utils;
+#[cfg(feature = "ipc")]
 pub use ipc::*;
+#[cfg(feature = "ipc")]
+pub use problem::*;
+#[cfg(feature = "ipc")]
+pub use utils::*;
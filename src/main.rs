@@ -1,9 +1,10 @@
-use core::str;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::error::Error;
+use std::net::TcpListener;
+use std::path::PathBuf;
 
-use clap::{builder::styling::AnsiColor, builder::Styles, Args, Parser, Subcommand};
+use clap::{builder::styling::AnsiColor, builder::Styles, Args, Parser, Subcommand, ValueEnum};
 use serde::Serialize;
+use serde_json::json;
 use tqdm::{Iter, Style};
 
 use codechecker::*;
@@ -36,23 +37,182 @@ enum Subcommands {
     Check(CheckArgs),
     /// Listen to the binded socket
     Listen(ListenArgs),
+    /// Serve an HTTP API for the judgment requests
+    Http(HttpArgs),
 }
 
 #[derive(Args, Debug)]
 struct CheckArgs {
-    /// Path to the executable to avaluate
+    /// Path to the executable to avaluate, or to its source with --language
     exe: String,
 
+    /// Compile the source given instead of the executable with this language
+    /// profile
+    #[arg(short, long)]
+    language: Option<String>,
+
     /// Time limit in milliseconds
     #[arg(short, long, default_value = "1000")]
     time: u64,
 
+    /// Wall time limit in milliseconds [default: 3 times the time limit]
+    #[arg(short, long)]
+    wall_time: Option<u64>,
+
     /// Memory limit in megabytes
     #[arg(short, long, default_value = "1024")]
     memory: u64,
 
+    /// Output limit in megabytes
+    #[arg(long, default_value_t = DEFAULT_OUTPUT_LIMIT)]
+    output_limit: u64,
+
+    /// How to print the result, the exit code is 0 when accepted, 1 when the
+    /// program couldn't be judged and 10 or more telling the verdict
+    /// otherwise
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+
     #[command(flatten)]
     input: InputArgs,
+
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// Readable text
+    Human,
+    /// A single JSON document with the verdict and the usage or report
+    Json,
+    /// A JSON line per test case, then a line with the verdict
+    Jsonl,
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// Parent cgroup v2 directory to run each test in its own cgroup, needs
+    /// Linux 5.19 or later
+    #[arg(long)]
+    cgroup: Option<PathBuf>,
+
+    /// Maximum amount of processes and threads when running in a cgroup
+    #[arg(long, default_value = "64")]
+    max_pids: u64,
+
+    /// Run in a sandbox with this directory as read-only root, the executable
+    /// path is then relative to it
+    #[arg(long)]
+    sandbox: Option<PathBuf>,
+
+    /// Make a host path visible in the sandbox as SOURCE:TARGET, read-only
+    /// unless followed by :rw
+    #[arg(long, requires = "sandbox", value_parser = parse_bind_mount)]
+    bind: Vec<BindMount>,
+
+    /// Where the working directory of each run is mounted in the sandbox, it
+    /// must exist in the sandbox root
+    #[arg(long, requires = "sandbox", default_value = "/tmp")]
+    sandbox_work_dir: PathBuf,
+
+    /// Where the files of a submission, like its compiled program, are
+    /// mounted in the sandbox, it must exist in the sandbox root
+    #[arg(long, requires = "sandbox", default_value = "/mnt")]
+    sandbox_build_dir: PathBuf,
+
+    /// Mount a tmpfs of this size in megabytes as the working directory of
+    /// each run, needs CAP_SYS_ADMIN
+    #[arg(long)]
+    tmpfs_size: Option<u64>,
+
+    /// Keep the working directory of runs that aren't accepted
+    #[arg(long)]
+    keep_failed: bool,
+
+    /// Pass this environment variable of the judge to the program, which
+    /// otherwise gets an empty environment
+    #[arg(long)]
+    pass_env: Vec<String>,
+
+    /// Set an environment variable of the program as NAME=VALUE, e.g.
+    /// ONLINE_JUDGE=1
+    #[arg(long, value_parser = parse_env)]
+    env: Vec<(String, String)>,
+
+    /// JSON file with a list of language profiles, added to the built-in
+    /// ones
+    #[arg(long)]
+    languages: Option<PathBuf>,
+}
+
+impl RunArgs {
+    fn config(&self) -> Result<RunConfig, Box<dyn Error>> {
+        let env = EnvPolicy {
+            pass: self.pass_env.clone(),
+            set: self.env.iter().cloned().collect(),
+        };
+        env.validate()?;
+
+        let languages = match &self.languages {
+            Some(path) => load_languages(path)
+                .map_err(|err| format!("Couldn't load the language profiles: {err}"))?,
+            None => vec![],
+        };
+
+        Ok(RunConfig {
+            cgroup: self.cgroup.as_ref().map(|parent| CgroupConfig {
+                parent: parent.clone(),
+                max_pids: self.max_pids,
+            }),
+            sandbox: self.sandbox.as_ref().map(|root| SandboxConfig {
+                root: root.clone(),
+                mounts: self.bind.clone(),
+                work_dir: self.sandbox_work_dir.clone(),
+                build_dir: self.sandbox_build_dir.clone(),
+                filter_syscalls: true,
+            }),
+            languages,
+            io: IoMode::Stdio,
+            scratch: ScratchConfig {
+                tmpfs_size: self.tmpfs_size,
+                keep_failed: self.keep_failed,
+            },
+            env,
+        })
+    }
+}
+
+fn parse_bind_mount(arg: &str) -> Result<BindMount, String> {
+    let parts: Vec<&str> = arg.split(':').collect();
+
+    match parts[..] {
+        [source, target] => Ok(BindMount {
+            source: PathBuf::from(source),
+            target: PathBuf::from(target),
+            writable: false,
+        }),
+        [source, target, "rw"] => Ok(BindMount {
+            source: PathBuf::from(source),
+            target: PathBuf::from(target),
+            writable: true,
+        }),
+        _ => Err(String::from("Expected SOURCE:TARGET or SOURCE:TARGET:rw")),
+    }
+}
+
+fn parse_env(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((String::from(name), String::from(value))),
+        _ => Err(String::from("Expected NAME=VALUE")),
+    }
+}
+
+fn parse_mode(arg: &str) -> Result<u32, String> {
+    u32::from_str_radix(arg, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| String::from("Expected permissions in octal, e.g. 660"))
 }
 
 #[derive(Args, Debug)]
@@ -69,199 +229,397 @@ struct InputArgs {
     /// Directory with test cases in the format #{case}.in #{case}.out
     #[arg(short, long, conflicts_with_all = ["input", "output"])]
     directory: Option<String>,
+
+    /// Run every test case of the directory instead of stopping at the first
+    /// failure, reporting the result of each one
+    #[arg(short, long, requires = "directory")]
+    all: bool,
 }
 
 #[derive(Debug, Args)]
 struct ListenArgs {
-    /// Socket address for incomming connections
+    /// Socket address for incomming connections, or unix:PATH to listen in a
+    /// Unix domain socket
     addr: String,
+
+    /// Permissions in octal of the Unix domain socket
+    #[arg(long, default_value = "660", value_parser = parse_mode)]
+    socket_mode: u32,
+
+    #[command(flatten)]
+    server: ServerArgs,
 }
 
-fn main() {
-    let cli = Cli::parse();
+#[derive(Debug, Args)]
+struct HttpArgs {
+    /// Socket address for incomming HTTP connections
+    addr: String,
 
-    match cli.command {
-        Subcommands::Check(CheckArgs {
-            exe,
-            time,
-            memory,
-            input,
-        }) => {
-            let cli_input = &input;
+    #[command(flatten)]
+    server: ServerArgs,
+}
 
-            if let Some(input) = cli_input.input.as_ref() {
-                let output = cli_input.output.as_ref().expect(
-                    "This should not had happened, --input and --output args require each other",
-                );
+#[derive(Debug, Args)]
+struct ServerArgs {
+    /// Amount of requests judged at the same time, at most the amount of CPU
+    /// cores [default: the amount of CPU cores]
+    #[arg(long)]
+    workers: Option<usize>,
+
+    /// JSON-lines file keeping the jobs and their results across restarts,
+    /// the requests not judged yet are kept next to it
+    #[arg(long)]
+    jobs: Option<PathBuf>,
+
+    /// Amount of finished jobs kept, the oldest ones are forgotten past it
+    #[arg(long, default_value_t = DEFAULT_MAX_DONE_JOBS)]
+    keep_jobs: usize,
+
+    /// Directory with a test directory per problem named by its id, with
+    /// the limits and checker in its problem.json. Requests then refer to
+    /// problems by id and send the program instead of giving paths on the
+    /// judge.
+    #[arg(long)]
+    problems: Option<PathBuf>,
+
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+impl ServerArgs {
+    fn config(&self) -> ServerConfig {
+        ServerConfig {
+            run: self.run.config().expect("Invalid run configuration"),
+            catalog: self
+                .problems
+                .as_ref()
+                .map(|root| ProblemCatalog::load(root).expect("Couldn't load the problem catalog")),
+        }
+    }
 
-                let checker = LinesChecker::new(output);
+    fn job_queue(&self) -> JobQueue {
+        let queue = match &self.jobs {
+            Some(path) => JobQueue::open(path).expect("Couldn't open the job store"),
+            None => JobQueue::new(),
+        };
 
-                if let Ok(verdict) = judge(&exe, None, input, time, memory, checker) {
-                    println!("{:?}", verdict);
+        queue.with_max_done(self.keep_jobs)
+    }
+}
+
+/// Exit code of `check` when the program couldn't be judged
+const ERROR_EXIT_CODE: i32 = 1;
+
+/// Exit code of `check` for a verdict, so scripts can tell verdicts apart
+/// without parsing the output. They start at 10 to stay clear of the usage
+/// errors and panics of the judge itself.
+fn exit_code(verdict: &ProblemVerdict) -> i32 {
+    match verdict {
+        ProblemVerdict::Accepted { .. } => 0,
+        ProblemVerdict::WrongAnswer { .. } => 10,
+        ProblemVerdict::PresentationError { .. } => 11,
+        ProblemVerdict::PartiallyCorrect { .. } => 12,
+        ProblemVerdict::TimeLimitExceeded => 13,
+        ProblemVerdict::WallTimeLimitExceeded => 14,
+        ProblemVerdict::MemoryLimitExceeded => 15,
+        ProblemVerdict::OutputLimitExceeded => 16,
+        ProblemVerdict::IdleLimitExceeded => 17,
+        ProblemVerdict::RuntimeError { .. } => 18,
+        ProblemVerdict::SecurityViolation => 19,
+        ProblemVerdict::CompilationError { .. } => 20,
+    }
+}
+
+fn print_json(value: &impl Serialize) {
+    println!(
+        "{}",
+        serde_json::to_string(value).expect("Couldn't serialize the result")
+    );
+}
+
+/// Judge a program from the command line, returning the exit code. Errors
+/// are printed in the requested format.
+fn check(args: CheckArgs) -> i32 {
+    let format = args.format;
+
+    match try_check(args) {
+        Ok(code) => code,
+        Err(err) => {
+            match format {
+                OutputFormat::Human => eprintln!("Error: {}", err),
+                OutputFormat::Json | OutputFormat::Jsonl => {
+                    print_json(&json!({ "error": err.to_string() }))
                 }
-            } else {
-                let directory = cli_input
-                    .directory
-                    .as_ref()
-                    .expect("This should not had happened, it no --input was given the --directory option should");
-
-                let numbers = sorted_list_numbers_in_folder(directory).unwrap();
-
-                let mut max_time: u64 = 0;
-                let mut max_memory: u64 = 0;
-                let mut res: Option<(ProblemVerdict, u32)> = None;
-
-                for num in numbers
-                    .into_iter()
-                    .tqdm()
-                    .desc(Some("Testing..."))
-                    .width(Some(100))
-                    .style(Style::Balloon)
-                {
-                    let input = format!("{}/{}.in", directory, num);
-                    let output = format!("{}/{}.out", directory, num);
-
-                    let checker = LinesChecker::new(&output);
-
-                    match judge(&exe, None, &input, time, memory, checker) {
-                        Ok(ProblemVerdict::Accepted { time, memory }) => {
-                            max_time = std::cmp::max(max_time, time);
-                            max_memory = std::cmp::max(max_memory, memory);
-                        }
-                        Ok(err_verdict) => {
-                            res = Some((err_verdict, num));
-                            break;
+            }
+            ERROR_EXIT_CODE
+        }
+    }
+}
+
+fn try_check(
+    CheckArgs {
+        exe,
+        language,
+        time,
+        wall_time,
+        memory,
+        output_limit,
+        format,
+        input,
+        run,
+    }: CheckArgs,
+) -> Result<i32, Box<dyn Error>> {
+    let mut config = run.config()?;
+    let limits = Limits {
+        time,
+        wall_time: wall_time.unwrap_or(default_wall_time(time)),
+        memory,
+        output: output_limit,
+    };
+    let cli_input = &input;
+
+    // Kept until the judgment is over
+    let build_dir;
+    let (exe, args) = match language {
+        Some(language) => {
+            let profile = config.language(&language).ok_or_else(|| {
+                format!("Unknown language {language:?}, check the language profiles")
+            })?;
+            let source = std::fs::read_to_string(&exe)
+                .map_err(|err| format!("Couldn't read the source: {err}"))?;
+            build_dir = BuildDir::new()
+                .map_err(|err| format!("Couldn't create the build directory: {err}"))?;
+
+            let compilation = profile.compile(&source, build_dir.path(), &config)?;
+            build_dir.mount(&mut config);
+            match compilation {
+                Compilation::Success(program) => (program.cmd, Some(program.args)),
+                Compilation::Error(log) => {
+                    let verdict = ProblemVerdict::CompilationError { log: log.clone() };
+                    match format {
+                        OutputFormat::Human => println!("Compilation error\n{}", log),
+                        OutputFormat::Json | OutputFormat::Jsonl => {
+                            print_json(&json!({ "verdict": verdict }))
                         }
-                        Err(err) => panic!("{:?}", err),
                     }
+                    return Ok(exit_code(&verdict));
                 }
+            }
+        }
+        None => (exe, None),
+    };
+
+    if let Some(input) = cli_input.input.as_ref() {
+        // Required along with --input
+        let output = cli_input.output.as_ref().ok_or("Missing --output")?;
+
+        let checker = LinesChecker::new(output);
+
+        let (verdict, usage, stderr) =
+            judge_with_usage(&exe, args, input, limits, &config, checker)?;
+        match format {
+            OutputFormat::Human => {
+                println!("{}", verdict_text(&verdict));
+                print_stderr(&stderr);
+            }
+            OutputFormat::Json | OutputFormat::Jsonl => print_json(&json!({
+                "verdict": verdict,
+                "usage": usage,
+                "stderr": stderr,
+            })),
+        }
+
+        return Ok(exit_code(&verdict));
+    }
+
+    // Required without --input
+    let directory = cli_input.directory.as_ref().ok_or("Missing --directory")?;
+
+    let problem = Problem::load(directory)?;
+    let mut numbers = sorted_list_numbers_in_folder(directory)?;
+    if let Some(problem) = &problem {
+        numbers = problem.test_cases(&numbers);
+    }
+    let checker = problem
+        .as_ref()
+        .map(|problem| problem.checker.clone())
+        .unwrap_or_default();
+    if let Some(problem) = &problem {
+        config.io = problem.io.clone();
+        config.env.merge(&problem.env);
+    }
+    // Every test case is needed to score the groups
+    let problem = problem.filter(Problem::is_scored);
+    let run_all = cli_input.all || problem.is_some();
+
+    // The progress bar is only for people reading the output
+    let test_cases: Box<dyn Iterator<Item = u32>> = match format {
+        OutputFormat::Human => Box::new(
+            numbers
+                .into_iter()
+                .tqdm()
+                .desc(Some("Testing..."))
+                .width(Some(100))
+                .style(Style::Balloon),
+        ),
+        OutputFormat::Json | OutputFormat::Jsonl => Box::new(numbers.into_iter()),
+    };
+
+    // JSON lines are printed as soon as each test case is judged
+    let report = judge_directory_with_checker(
+        &exe,
+        args,
+        directory,
+        test_cases,
+        limits,
+        &config,
+        &checker,
+        run_all,
+        |test| {
+            if let OutputFormat::Jsonl = format {
+                print_json(test);
+            }
+        },
+    )?;
+    let score = problem.map(|problem| problem.score(&report));
+    let code = exit_code(&report.verdict);
+
+    match format {
+        OutputFormat::Json => print_json(&json!({
+            "verdict": report.verdict,
+            "report": report,
+            "score": score,
+        })),
+        OutputFormat::Jsonl => {
+            print_json(&json!({
+                "verdict": report.verdict,
+                "failed_test_case": report.failed_test_case,
+                "passed": report.passed,
+                "total": report.total,
+                "score": score,
+            }));
+        }
+        OutputFormat::Human => print_report(report, score, cli_input.all),
+    }
+
+    Ok(code)
+}
+
+/// Verdict for people, runtime errors tell how the program ended and leave
+/// the stderr to `print_stderr`
+fn verdict_text(verdict: &ProblemVerdict) -> String {
+    match verdict {
+        ProblemVerdict::RuntimeError { termination, .. } => {
+            format!("RuntimeError: {}", termination)
+        }
+        verdict => format!("{:?}", verdict),
+    }
+}
+
+fn print_stderr(stderr: &Option<String>) {
+    if let Some(stderr) = stderr {
+        println!("Stderr:\n{}", stderr.trim_end());
+    }
+}
 
-                if res.is_none() {
-                    res = Some((
-                        ProblemVerdict::Accepted {
-                            time: max_time,
-                            memory: max_memory,
-                        },
-                        0,
-                    ));
+fn print_report(report: TestReport, score: Option<ScoreReport>, all: bool) {
+    if all {
+        for test in &report.tests {
+            println!(
+                "Test case {}: {} cpu time = {}, wall time = {}, memory = {}",
+                test.test_case,
+                verdict_text(&test.verdict),
+                test.usage.cpu_time,
+                test.usage.wall_time,
+                test.usage.memory
+            );
+        }
+        println!("Passed {} of {} test cases", report.passed, report.total);
+    }
+
+    if let Some(score) = score {
+        for group in &score.groups {
+            let note = if group.dependencies_failed {
+                " (dependencies failed)"
+            } else {
+                ""
+            };
+            println!(
+                "Group {}: {} of {} points{}",
+                group.name, group.score, group.points, note
+            );
+        }
+        println!("Score {} of {}", score.score, score.max_score);
+    }
+
+    match (report.verdict, report.failed_test_case) {
+        (
+            ProblemVerdict::Accepted {
+                cpu_time,
+                wall_time,
+                memory,
+            },
+            _,
+        ) => {
+            println!(
+                "Accepted cpu time = {}, wall time = {}, memory = {}",
+                cpu_time, wall_time, memory
+            )
+        }
+        (verdict, Some(test_case)) => {
+            println!("{} on test case {}", verdict_text(&verdict), test_case);
+            if let Some(test) = report.tests.iter().find(|t| t.test_case == test_case) {
+                print_stderr(&test.stderr);
+            }
+        }
+        (_, None) => println!("WTF? Why are we here, this shouldn't be happening"),
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Subcommands::Check(args) => std::process::exit(check(args)),
+
+        Subcommands::Listen(ListenArgs {
+            addr,
+            socket_mode,
+            server,
+        }) => {
+            let config = server.config();
+            let workers = worker_count(server.workers);
+            let queue = server.job_queue();
+
+            match addr.strip_prefix("unix:") {
+                Some(path) => {
+                    let listener = bind_unix(path, socket_mode)
+                        .expect("Couldn't bind to the given socket path");
+
+                    println!("Listening in {path:?} with {workers} workers");
+                    let incoming = listener.incoming().map(|s| s.map(Connection::Unix));
+                    serve(incoming, &queue, workers, &config);
                 }
+                None => {
+                    let listener = TcpListener::bind(&addr)
+                        .expect("Couldn't bind to the given socket address");
 
-                match res {
-                    Some((ProblemVerdict::Accepted { time, memory }, _)) => {
-                        println!("Accepted time = {}, memory = {}", time, memory)
-                    }
-                    Some((verdict, test_case)) => {
-                        println!("{:?} on test case {}", verdict, test_case)
-                    }
-                    None => println!("WTF? Why are we here, this shouldn't be happening"),
+                    println!("Listening in {addr:?} with {workers} workers");
+                    let incoming = listener.incoming().map(|s| s.map(Connection::Tcp));
+                    serve(incoming, &queue, workers, &config);
                 }
             }
         }
 
-        Subcommands::Listen(ListenArgs { addr }) => {
+        Subcommands::Http(HttpArgs { addr, server }) => {
+            let config = server.config();
+            let workers = worker_count(server.workers);
+            let queue = server.job_queue();
             let listener =
                 TcpListener::bind(&addr).expect("Couldn't bind to the given socket address");
 
-            println!("Listening in {addr:?}");
-            match listener.accept() {
-                Ok((mut stream, _addr)) => {
-                    let mut buf = [0; 1024];
-                    if let Ok(bytes_read) = stream.read(&mut buf) {
-                        let text = str::from_utf8(&buf[0..bytes_read])
-                            .expect("Error parsing string from utf8");
-                        let request: JudeRequest = serde_json::from_str(text)
-                            .expect("Couldn't deserialize string into a judge request");
-
-                        let JudeRequest {
-                            cmd,
-                            cmd_args,
-                            time,
-                            memory,
-                            test_dir: directory,
-                        } = request;
-
-                        match sorted_list_numbers_in_folder(&directory) {
-                            Ok(numbers) => {
-                                let mut max_time: u64 = 0;
-                                let mut max_memory: u64 = 0;
-                                let mut res: Option<(ProblemVerdict, u32)> = None;
-
-                                for num in numbers {
-                                    let input = format!("{}/{}.in", directory, num);
-                                    let output = format!("{}/{}.out", directory, num);
-
-                                    let checker = LinesChecker::new(&output);
-
-                                    send(&mut stream, &JudgeResponse::test_case(num));
-                                    match judge(
-                                        &cmd,
-                                        cmd_args.clone(),
-                                        &input,
-                                        time,
-                                        memory,
-                                        checker,
-                                    ) {
-                                        Ok(ProblemVerdict::Accepted { time, memory }) => {
-                                            max_time = std::cmp::max(max_time, time);
-                                            max_memory = std::cmp::max(max_memory, memory);
-                                        }
-                                        Ok(err_verdict) => {
-                                            res = Some((err_verdict, num));
-                                            break;
-                                        }
-                                        Err(err) => {
-                                            let response = JudgeResponse::error(
-                                                "Error while judging, check checker's log",
-                                            );
-                                            send(&mut stream, &response);
-                                            panic!("{:?}", err);
-                                        }
-                                    }
-                                }
-
-                                if res.is_none() {
-                                    res = Some((
-                                        ProblemVerdict::Accepted {
-                                            time: max_time,
-                                            memory: max_memory,
-                                        },
-                                        0,
-                                    ));
-                                }
-
-                                match res {
-                                    Some(ver) => {
-                                        send(&mut stream, &JudgeResponse::ok(ver.0));
-                                    }
-                                    None => {
-                                        send(
-                                            &mut stream,
-                                            &JudgeResponse::error("Unexpected error"),
-                                        );
-
-                                        println!(
-                                            "WTF? Why are we here, this shouldn't be happening"
-                                        )
-                                    }
-                                }
-                            }
-                            Err(err) => {
-                                send(&mut stream, &JudgeResponse::error("Can't open directory"));
-                                panic!("{:?}", err);
-                            }
-                        }
-                    } else {
-                        panic!("There was an error reading from the socket");
-                    }
-                }
-                Err(e) => println!("Couldn't get client {e}"),
-            }
+            println!("Serving HTTP in {addr:?} with {workers} workers");
+            serve_http(listener, &queue, workers, &config);
         }
     }
 }
-
-fn send<T: ?Sized + Serialize>(stream: &mut TcpStream, response: &T) {
-    stream
-        .write(serde_json::to_string(&response).unwrap().as_bytes())
-        .expect("Couldn't send response to caller");
-}
@@ -0,0 +1,455 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::utils::{
+    default_wall_time, sorted_list_numbers_in_folder, EnvPolicy, Limits, ProblemVerdict,
+    TestReport, DEFAULT_OUTPUT_LIMIT,
+};
+
+/// Name of the problem descriptor inside a test directory
+pub const PROBLEM_FILE: &str = "problem.json";
+
+/// Problem descriptor, splitting the test cases into scored groups
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Problem {
+    /// Scored groups, without them every test case is judged and the problem
+    /// isn't scored
+    #[serde(default)]
+    pub groups: Vec<TestGroup>,
+    /// Limits of each run, required for the problems of a catalog
+    pub limits: Option<ProblemLimits>,
+    #[serde(default)]
+    pub checker: CheckerConfig,
+    #[serde(default)]
+    pub io: IoMode,
+    /// Added to the environment the judge gives to the program
+    #[serde(default)]
+    pub env: EnvPolicy,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ProblemLimits {
+    /// CPU time limit in ms
+    pub time: u64,
+    /// Wall time limit in ms, `default_wall_time(time)` if not given
+    pub wall_time: Option<u64>,
+    /// Memory limit in Mb
+    pub memory: u64,
+    /// Output limit in Mb, `DEFAULT_OUTPUT_LIMIT` if not given
+    pub output: Option<u64>,
+}
+
+impl ProblemLimits {
+    pub fn limits(&self) -> Limits {
+        Limits {
+            time: self.time,
+            wall_time: self.wall_time.unwrap_or(default_wall_time(self.time)),
+            memory: self.memory,
+            output: self.output.unwrap_or(DEFAULT_OUTPUT_LIMIT),
+        }
+    }
+}
+
+/// How the output of each test case is checked against `{test_case}.out`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum CheckerConfig {
+    /// Same lines, ignoring trailing whitespace
+    #[default]
+    Lines,
+    /// Same tokens, with numbers compared up to an absolute or relative error
+    Tokens { abs_eps: f64, rel_eps: f64 },
+    /// Testlib checker, its path relative to the test directory
+    External {
+        path: String,
+        /// CPU time limit of the checker in ms
+        #[serde(default = "default_checker_time")]
+        time: u64,
+        /// Memory limit of the checker in Mb
+        #[serde(default = "default_checker_memory")]
+        memory: u64,
+    },
+}
+
+/// Where the judged program reads its input and writes its output, ignored by
+/// interactive problems
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum IoMode {
+    /// Standard input and output
+    #[default]
+    Stdio,
+    /// Files in the working directory of the program, which is created for
+    /// each run with only the input file in it
+    Files {
+        #[serde(default = "default_input_file")]
+        input: String,
+        #[serde(default = "default_output_file")]
+        output: String,
+    },
+}
+
+fn default_input_file() -> String {
+    String::from("input.txt")
+}
+
+fn default_output_file() -> String {
+    String::from("output.txt")
+}
+
+fn default_checker_time() -> u64 {
+    10000
+}
+
+fn default_checker_memory() -> u64 {
+    1024
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestGroup {
+    pub name: String,
+    /// Test cases of the group, each one a number (`"3"`), an inclusive range
+    /// (`"1-10"`) or a glob over the test number (`"1?"`)
+    pub tests: Vec<String>,
+    pub points: f64,
+    #[serde(default)]
+    pub scoring: Scoring,
+    /// Groups whose test cases must all be accepted for this one to be
+    /// scored, they have to be declared before it
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+}
+
+/// How the score of a group is computed from its test cases, where an
+/// accepted test case scores 1, a partially correct one its points (expected
+/// between 0 and 1) and anything else 0
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scoring {
+    /// All the points if every test case is accepted, none otherwise
+    #[default]
+    AllOrNothing,
+    /// The points scaled by the lowest test case score
+    Min,
+    /// The points split evenly among the test cases
+    Sum,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupScore {
+    pub name: String,
+    pub score: f64,
+    pub points: f64,
+    pub tests: Vec<u32>,
+    /// Not scored since a group it depends on didn't have all its test cases
+    /// accepted
+    pub dependencies_failed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoreReport {
+    pub score: f64,
+    pub max_score: f64,
+    pub groups: Vec<GroupScore>,
+}
+
+impl Problem {
+    /// Load the problem descriptor of a test directory, if it has one
+    pub fn load(directory: &str) -> Result<Option<Self>, Box<dyn Error>> {
+        let path = Path::new(directory).join(PROBLEM_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let problem: Problem = serde_json::from_str(&fs::read_to_string(path)?)?;
+        problem.validate()?;
+
+        Ok(Some(problem))
+    }
+
+    /// Whether the problem has groups to score
+    pub fn is_scored(&self) -> bool {
+        !self.groups.is_empty()
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        self.env.validate()?;
+
+        if let IoMode::Files { input, output } = &self.io {
+            for name in [input, output] {
+                if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+                    return Err(
+                        format!("Invalid file name {:?}, expected a plain name", name).into(),
+                    );
+                }
+            }
+        }
+
+        for (i, group) in self.groups.iter().enumerate() {
+            for dependency in &group.depends_on {
+                if !self.groups[..i].iter().any(|g| &g.name == dependency) {
+                    return Err(format!(
+                        "Group {:?} depends on {:?}, which is not declared before it",
+                        group.name, dependency
+                    )
+                    .into());
+                }
+            }
+
+            for spec in &group.tests {
+                if !is_valid_spec(spec) {
+                    return Err(format!(
+                        "Invalid test cases {:?} in group {:?}, expected a number, a range or a glob",
+                        spec, group.name
+                    )
+                    .into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Test cases of a group among the available ones
+    pub fn group_tests(group: &TestGroup, available: &[u32]) -> Vec<u32> {
+        available
+            .iter()
+            .copied()
+            .filter(|&test_case| group.tests.iter().any(|spec| matches_spec(spec, test_case)))
+            .collect()
+    }
+
+    /// Every test case used by some group among the available ones, sorted,
+    /// or all of them if the problem isn't scored
+    pub fn test_cases(&self, available: &[u32]) -> Vec<u32> {
+        if !self.is_scored() {
+            return available.to_vec();
+        }
+
+        let test_cases: BTreeSet<u32> = self
+            .groups
+            .iter()
+            .flat_map(|group| Self::group_tests(group, available))
+            .collect();
+
+        test_cases.into_iter().collect()
+    }
+
+    /// Score each group with the results of the report
+    pub fn score(&self, report: &TestReport) -> ScoreReport {
+        let available: Vec<u32> = report.tests.iter().map(|test| test.test_case).collect();
+        let mut groups: Vec<GroupScore> = Vec::new();
+        // Fraction of the points of each group, a group with no points still
+        // fails its dependents unless all its test cases are accepted
+        let mut fractions: BTreeMap<&str, f64> = BTreeMap::new();
+
+        for group in &self.groups {
+            let tests = Self::group_tests(group, &available);
+
+            let dependencies_failed = group.depends_on.iter().any(|dependency| {
+                fractions
+                    .get(dependency.as_str())
+                    .is_some_and(|&fraction| fraction < 1.0)
+            });
+
+            let scores: Vec<f64> = tests
+                .iter()
+                .map(|&test_case| {
+                    report
+                        .tests
+                        .iter()
+                        .find(|test| test.test_case == test_case)
+                        .map_or(0.0, |test| test_score(&test.verdict))
+                })
+                .collect();
+
+            let fraction = if dependencies_failed || scores.is_empty() {
+                0.0
+            } else {
+                match group.scoring {
+                    Scoring::AllOrNothing => {
+                        if scores.iter().all(|&score| score >= 1.0) {
+                            1.0
+                        } else {
+                            0.0
+                        }
+                    }
+                    Scoring::Min => scores.iter().copied().fold(1.0, f64::min),
+                    Scoring::Sum => scores.iter().sum::<f64>() / scores.len() as f64,
+                }
+            };
+
+            fractions.insert(&group.name, fraction);
+            groups.push(GroupScore {
+                name: group.name.clone(),
+                score: group.points * fraction,
+                points: group.points,
+                tests,
+                dependencies_failed,
+            });
+        }
+
+        ScoreReport {
+            score: groups.iter().map(|group| group.score).sum(),
+            max_score: groups.iter().map(|group| group.points).sum(),
+            groups,
+        }
+    }
+}
+
+/// Problem of a catalog, ready to be judged
+#[derive(Debug, Clone)]
+pub struct CatalogProblem {
+    pub directory: String,
+    pub problem: Problem,
+    pub limits: Limits,
+    /// Test cases judged, in order
+    pub test_cases: Vec<u32>,
+}
+
+/// What clients can know about a problem of the catalog
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProblemInfo {
+    pub id: String,
+    pub limits: Limits,
+    pub test_cases: u32,
+    /// Maximum score, `None` if the problem isn't scored
+    pub max_score: Option<f64>,
+    pub io: IoMode,
+}
+
+/// Problems served by a judge, loaded once at startup from a directory with a
+/// test directory per problem, named by its id. Only directories with a
+/// problem descriptor are part of the catalog.
+#[derive(Debug, Clone, Default)]
+pub struct ProblemCatalog {
+    problems: BTreeMap<String, CatalogProblem>,
+}
+
+impl ProblemCatalog {
+    pub fn load(root: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut problems = BTreeMap::new();
+
+        for entry in fs::read_dir(root)? {
+            let path = entry?.path();
+            let Some(id) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if !path.is_dir() || !is_valid_id(id) {
+                continue;
+            }
+
+            let directory = path.to_string_lossy().into_owned();
+            let problem = match Problem::load(&directory) {
+                Ok(Some(problem)) => problem,
+                Ok(None) => continue,
+                Err(err) => return Err(format!("Invalid problem {:?}: {}", id, err).into()),
+            };
+            let limits = problem
+                .limits
+                .ok_or_else(|| format!("Problem {:?} has no limits", id))?
+                .limits();
+
+            let test_cases = problem.test_cases(&sorted_list_numbers_in_folder(&directory)?);
+            if test_cases.is_empty() {
+                return Err(format!("Problem {:?} has no test cases", id).into());
+            }
+
+            problems.insert(
+                id.to_string(),
+                CatalogProblem {
+                    directory,
+                    problem,
+                    limits,
+                    test_cases,
+                },
+            );
+        }
+
+        Ok(Self { problems })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&CatalogProblem> {
+        self.problems.get(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.problems.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Every problem of the catalog, sorted by id
+    pub fn list(&self) -> Vec<ProblemInfo> {
+        self.problems
+            .iter()
+            .map(|(id, entry)| ProblemInfo {
+                id: id.clone(),
+                limits: entry.limits,
+                test_cases: entry.test_cases.len() as u32,
+                max_score: entry
+                    .problem
+                    .is_scored()
+                    .then(|| entry.problem.groups.iter().map(|group| group.points).sum()),
+                io: entry.problem.io.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Problem ids can't be used to escape the catalog directory
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn test_score(verdict: &ProblemVerdict) -> f64 {
+    match verdict {
+        ProblemVerdict::Accepted { .. } => 1.0,
+        ProblemVerdict::PartiallyCorrect { points, .. } => points.clamp(0.0, 1.0),
+        _ => 0.0,
+    }
+}
+
+/// Whether a test case spec is a number, a range or a glob over the number
+fn is_valid_spec(spec: &str) -> bool {
+    parse_range(spec).is_some()
+        || (!spec.is_empty()
+            && spec
+                .chars()
+                .all(|c| c.is_ascii_digit() || c == '*' || c == '?'))
+}
+
+fn parse_range(spec: &str) -> Option<(u32, u32)> {
+    let (start, end) = spec.split_once('-')?;
+    Some((start.trim().parse().ok()?, end.trim().parse().ok()?))
+}
+
+fn matches_spec(spec: &str, test_case: u32) -> bool {
+    if let Some((start, end)) = parse_range(spec) {
+        return (start..=end).contains(&test_case);
+    }
+
+    matches_glob(spec.as_bytes(), test_case.to_string().as_bytes())
+}
+
+/// Match `text` against a glob `pattern` supporting `*` and `?`
+fn matches_glob(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            matches_glob(&pattern[1..], text)
+                || (!text.is_empty() && matches_glob(pattern, &text[1..]))
+        }
+        (Some(b'?'), Some(_)) => matches_glob(&pattern[1..], &text[1..]),
+        (Some(p), Some(t)) if p == t => matches_glob(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
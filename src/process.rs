@@ -4,17 +4,22 @@ use nix::sys::signal::kill;
 use nix::sys::signal::Signal;
 use std::cmp::{max, min};
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
 use std::thread::sleep;
 use std::time::{Duration, SystemTime};
 
 pub use nix::unistd::Pid;
 
+use crate::cgroup::Cgroup;
+use crate::sandbox::Supervisor;
+
 #[derive(Debug)]
 pub enum ProcessState {
     Running,
     Exited(i32),
     TimeLimitExceeded,
+    WallTimeLimitExceeded,
     MemoryLimitExceeded,
     IdleLimitExceeded,
     RuntimeError(i32),
@@ -25,24 +30,100 @@ pub enum ProcessState {
 pub struct Process {
     pid: Pid,
     time_limit_ms: u64,
+    wall_time_limit_ms: u64,
     memory_limit_mb: u64,
     pub consumed_time_ms: u64,
+    pub consumed_wall_time_ms: u64,
     pub consumed_memory_mb: u64,
     idle_count: u32,
     pub state: ProcessState,
+    /// When given the resource usage is read from the cgroup instead of
+    /// `/proc`, so children of the process are accounted for too
+    pub cgroup: Option<Cgroup>,
+    /// When given the usage without a cgroup is read from the sandboxed
+    /// program instead of the process supervising it
+    pub(crate) supervisor: Option<Supervisor>,
 }
 
 impl Process {
-    pub fn new(pid: Pid, time_limit_ms: u64, memory_limit_mb: u64) -> Self {
+    pub fn new(
+        pid: Pid,
+        time_limit_ms: u64,
+        wall_time_limit_ms: u64,
+        memory_limit_mb: u64,
+    ) -> Self {
         Process {
             pid,
             time_limit_ms,
+            wall_time_limit_ms,
             memory_limit_mb,
             consumed_time_ms: 0,
+            consumed_wall_time_ms: 0,
             consumed_memory_mb: 0,
             idle_count: 0,
             state: ProcessState::Running,
+            cgroup: None,
+            supervisor: None,
+        }
+    }
+}
+
+/// Handle to a process that keeps referring to it after it's reaped, so it
+/// can be signaled from other threads without hitting a process that reused
+/// its pid
+#[derive(Debug)]
+pub struct PidFd(OwnedFd);
+
+impl PidFd {
+    /// Open a handle to `pid`, which must not have been reaped yet
+    pub fn open(pid: Pid) -> io::Result<Self> {
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd as i32) }))
+    }
+
+    pub fn kill(&self) -> io::Result<()> {
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                self.0.as_raw_fd(),
+                libc::SIGKILL,
+                std::ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Block until the process exits, without reaping it
+    pub fn wait_exit(&self) -> io::Result<()> {
+        let mut poll_fd = libc::pollfd {
+            fd: self.0.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        while unsafe { libc::poll(&mut poll_fd, 1, -1) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
         }
+
+        Ok(())
+    }
+}
+
+impl From<OwnedFd> for PidFd {
+    fn from(fd: OwnedFd) -> Self {
+        Self(fd)
     }
 }
 
@@ -52,41 +133,61 @@ fn ticks_to_ms(ticks: u64) -> u64 {
     ticks * 1000 / ticks_per_sec
 }
 
-fn read_stat(pid: Pid) -> u64 {
+fn read_stat(pid: Pid) -> io::Result<u64> {
     let path = format!("/proc/{}/stat", pid);
-    let file = File::open(path).expect("Failed to open stat file");
+    let file = File::open(path)?;
     let mut reader = BufReader::new(file);
     let mut line = String::new();
-    reader.read_line(&mut line).unwrap();
+    reader.read_line(&mut line)?;
 
     let values: Vec<&str> = line.split_whitespace().collect();
     let utime: u64 = values[13].parse().unwrap();
     let stime: u64 = values[14].parse().unwrap();
 
-    ticks_to_ms(utime + stime)
+    Ok(ticks_to_ms(utime + stime))
 }
 
-fn read_memory_usage(pid: Pid) -> u64 {
+fn read_memory_usage(pid: Pid) -> io::Result<u64> {
     let path = format!("/proc/{}/status", pid);
-    let file = File::open(path).expect("Failed to open status file");
+    let file = File::open(path)?;
     let reader = BufReader::new(file);
 
     for line in reader.lines() {
-        let line = line.unwrap();
+        let line = line?;
         if line.starts_with("VmPeak:") {
             let parts: Vec<&str> = line.split_whitespace().collect();
             let value_kb: u64 = parts[1].parse().unwrap();
-            return value_kb / 1024; // Return un Mb
+            return Ok(value_kb / 1024); // Return un Mb
         }
     }
 
-    0
+    Ok(0)
 }
 
-/// Update process with /proc/{pid}/ files
+/// Update process with its cgroup or /proc/{pid}/ files
 fn update(process: &mut Process) {
-    let cpu_time = read_stat(process.pid);
-    let memory = read_memory_usage(process.pid);
+    let (cpu_time, memory) = match &process.cgroup {
+        Some(cgroup) => (
+            cgroup.cpu_time_ms().unwrap_or(0),
+            cgroup.memory_peak_mb().unwrap_or(0),
+        ),
+        None => {
+            let pid = process
+                .supervisor
+                .as_ref()
+                .and_then(Supervisor::program_pid)
+                .unwrap_or(process.pid);
+            match (read_stat(pid), read_memory_usage(pid)) {
+                (Ok(cpu_time), Ok(memory)) => (cpu_time, memory),
+                // The sandboxed program exits before its supervisor
+                _ if pid != process.pid => return,
+                (cpu_time, memory) => (
+                    cpu_time.expect("Failed to read stat file"),
+                    memory.expect("Failed to read status file"),
+                ),
+            }
+        }
+    };
 
     if process.consumed_time_ms == cpu_time {
         process.idle_count += 1;
@@ -107,12 +208,52 @@ fn update_with_rusage(process: &mut Process, usage: &rusage) {
 
     process.consumed_time_ms = max(process.consumed_time_ms, total_time);
     process.consumed_memory_mb = max(process.consumed_memory_mb, usage.ru_maxrss as u64 / 1024);
+
+    // The cgroup also accounts for the children still running
+    if let Some(cgroup) = &process.cgroup {
+        if let Ok(cpu_time) = cgroup.cpu_time_ms() {
+            process.consumed_time_ms = max(process.consumed_time_ms, cpu_time);
+        }
+        if let Ok(memory) = cgroup.memory_peak_mb() {
+            process.consumed_memory_mb = max(process.consumed_memory_mb, memory);
+        }
+    }
+}
+
+/// Whether the memory limit was exceeded, either by what was measured or by
+/// the kernel killing something in the cgroup
+fn is_memory_exceeded(process: &Process) -> bool {
+    let oom_killed = process
+        .cgroup
+        .as_ref()
+        .is_some_and(|cgroup| cgroup.oom_killed());
+
+    oom_killed
+        || (process.consumed_memory_mb > process.memory_limit_mb && process.memory_limit_mb > 0)
 }
 
-fn is_idle(process: &Process, elapsed_time: u64) -> bool {
-    process.idle_count > 100 || (elapsed_time > 5000 && elapsed_time > 10 * process.time_limit_ms)
+fn is_idle(process: &Process) -> bool {
+    process.idle_count > 100
 }
 
+/// Kill the process and reap it, so it doesn't stay around as a zombie
+fn terminate(process: &mut Process, state: ProcessState) {
+    if kill(process.pid, Signal::SIGKILL).is_err() {
+        process.state = ProcessState::Failed;
+        return;
+    }
+    process.state = state;
+
+    let mut status = 0;
+    while unsafe { libc::waitpid(process.pid.as_raw(), &mut status, 0) } < 0 {
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            break;
+        }
+    }
+}
+
+/// Wait until the process finishes or goes over a limit, in which case it's
+/// killed. Either way the process is reaped when this returns.
 pub fn wait_for(process: &mut Process) {
     let start_time = SystemTime::now();
     let mut iter = 0;
@@ -132,70 +273,74 @@ pub fn wait_for(process: &mut Process) {
             )
         };
 
+        process.consumed_wall_time_ms = start_time.elapsed().unwrap().as_millis() as u64;
+
         if wait_result == 0 {
             update(process);
 
             if process.consumed_time_ms > process.time_limit_ms && process.time_limit_ms > 0 {
-                process.state = ProcessState::TimeLimitExceeded;
-                if kill(process.pid, Signal::SIGKILL).is_err() {
-                    process.state = ProcessState::Failed;
-                }
-                return;
+                return terminate(process, ProcessState::TimeLimitExceeded);
             }
 
-            if process.consumed_memory_mb > process.memory_limit_mb && process.memory_limit_mb > 0 {
-                process.state = ProcessState::MemoryLimitExceeded;
-                if kill(process.pid, Signal::SIGKILL).is_err() {
-                    process.state = ProcessState::Failed;
-                }
-                return;
+            if is_memory_exceeded(process) {
+                return terminate(process, ProcessState::MemoryLimitExceeded);
             }
 
-            let elapsed_time = start_time.elapsed().unwrap().as_millis() as u64;
-            if is_idle(process, elapsed_time) {
-                process.state = ProcessState::IdleLimitExceeded;
-                if kill(process.pid, Signal::SIGKILL).is_err() {
-                    process.state = ProcessState::Failed;
-                }
-                return;
+            if process.consumed_wall_time_ms > process.wall_time_limit_ms
+                && process.wall_time_limit_ms > 0
+            {
+                return terminate(process, ProcessState::WallTimeLimitExceeded);
             }
 
-            sleep(Duration::from_millis(min(100 * iter, 1000) as u64));
-        } else {
-            update_with_rusage(process, &usage);
+            if is_idle(process) {
+                return terminate(process, ProcessState::IdleLimitExceeded);
+            }
 
-            if process.consumed_time_ms > process.time_limit_ms && process.time_limit_ms > 0 {
-                process.state = ProcessState::TimeLimitExceeded;
-                if kill(process.pid, Signal::SIGKILL).is_err() {
-                    process.state = ProcessState::Failed;
-                }
+            // Poll often at first so the wall time of short runs is accurate,
+            // and don't oversleep the wall time limit
+            let mut sleep_ms = min(1 << min(iter, 10), 1000) as u64;
+            if process.wall_time_limit_ms > 0 {
+                let remaining = process.wall_time_limit_ms - process.consumed_wall_time_ms;
+                sleep_ms = min(sleep_ms, remaining.saturating_add(1));
+            }
+            sleep(Duration::from_millis(sleep_ms));
+        } else {
+            if wait_result == -1 {
+                process.state = ProcessState::Failed;
                 return;
             }
 
-            if process.consumed_memory_mb > process.memory_limit_mb && process.memory_limit_mb > 0 {
-                process.state = ProcessState::MemoryLimitExceeded;
-                if kill(process.pid, Signal::SIGKILL).is_err() {
-                    process.state = ProcessState::Failed;
+            update_with_rusage(process, &usage);
+
+            // A process that exited or was killed is already reaped, only a
+            // stopped or continued one is still there
+            let reaped = libc::WIFEXITED(status) || libc::WIFSIGNALED(status);
+            let finish = |process: &mut Process, state| {
+                if reaped {
+                    process.state = state;
+                } else {
+                    terminate(process, state);
                 }
-                return;
+            };
+
+            if process.consumed_time_ms > process.time_limit_ms && process.time_limit_ms > 0 {
+                return finish(process, ProcessState::TimeLimitExceeded);
             }
 
-            if wait_result == -1 {
-                process.state = ProcessState::Failed;
-                return;
+            if is_memory_exceeded(process) {
+                return finish(process, ProcessState::MemoryLimitExceeded);
             }
 
-            if wait_result == process.pid.into() && libc::WIFSTOPPED(status) {
-                process.state = ProcessState::RuntimeError(libc::WSTOPSIG(status));
-                return;
+            if wait_result == process.pid.as_raw() && libc::WIFSTOPPED(status) {
+                return finish(process, ProcessState::RuntimeError(libc::WSTOPSIG(status)));
             }
 
-            if wait_result == process.pid.into() && libc::WIFSIGNALED(status) {
+            if wait_result == process.pid.as_raw() && libc::WIFSIGNALED(status) {
                 process.state = ProcessState::RuntimeError(libc::WTERMSIG(status));
                 return;
             }
 
-            if wait_result == process.pid.into() && libc::WIFEXITED(status) {
+            if wait_result == process.pid.as_raw() && libc::WIFEXITED(status) {
                 process.state = ProcessState::Exited(libc::WEXITSTATUS(status));
                 return;
             }
@@ -204,7 +349,7 @@ pub fn wait_for(process: &mut Process) {
                 "Unexpected process state: wait4_result={}, status={}",
                 wait_result, status
             );
-            process.state = ProcessState::Failed;
+            finish(process, ProcessState::Failed);
         }
     }
 }
@@ -0,0 +1,607 @@
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use libc::{sock_filter, sock_fprog};
+
+use crate::process::{Pid, PidFd};
+use crate::seccomp::{seccomp_filter, Filter};
+
+/// Isolation for untrusted programs, applied between fork and exec
+///
+/// The program runs in new user, mount, network, IPC, UTS and PID namespaces,
+/// chrooted into a read-only root, without capabilities and, unless turned
+/// off, with a seccomp filter that kills it on any syscall outside an
+/// allowlist. Since the root changes before exec, the command to run must be
+/// a path inside the root.
+#[derive(Debug, Clone)]
+pub struct SandboxConfig {
+    /// Directory mounted read-only as the root filesystem of the program, it
+    /// must contain whatever the program needs to run (e.g. shared libraries)
+    pub root: PathBuf,
+    /// Host paths made visible inside the root
+    pub mounts: Vec<BindMount>,
+    /// Absolute path inside the root where the working directory of each run
+    /// is mounted writable, it must already exist there
+    pub work_dir: PathBuf,
+    /// Absolute path inside the root where the files of a submission are
+    /// mounted, writable while compiling and read-only while judging. It must
+    /// already exist there.
+    pub build_dir: PathBuf,
+    /// Kill the program on any syscall outside the allowlist, which also
+    /// keeps it from starting processes. Compilers run without it.
+    pub filter_syscalls: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct BindMount {
+    /// Path in the host
+    pub source: PathBuf,
+    /// Absolute path inside the root, it must already exist there
+    pub target: PathBuf,
+    pub writable: bool,
+}
+
+/// Everything the sandbox needs prepared before forking, since allocating
+/// between fork and exec is not allowed
+struct Prepared {
+    root: CString,
+    root_flags: libc::c_ulong,
+    mounts: Vec<(CString, CString, Option<libc::c_ulong>)>,
+    /// Directory to start in, relative to the root
+    cwd: CString,
+    uid_map: String,
+    gid_map: String,
+    filter: Option<Filter>,
+    /// Where to mount the `/proc` of the new PID namespace, if the root has it
+    proc_dir: Option<CString>,
+    /// Where the init of the namespace sends the program to the judge
+    socket: OwnedFd,
+}
+
+/// The judge's side of a sandboxed run
+///
+/// The spawned process only forwards the exit status of the program, which
+/// runs as the child of the init of the new PID namespace. Its usage has to
+/// be read from the program itself.
+#[derive(Debug)]
+pub struct Supervisor {
+    socket: OwnedFd,
+    program_pid: Option<Pid>,
+    program_pidfd: Option<PidFd>,
+}
+
+impl Supervisor {
+    /// Receive the program from the init, which sends it before the spawn
+    /// returns
+    pub(crate) fn find_program(&mut self) {
+        let mut byte = 0u8;
+        let mut iov = libc::iovec {
+            iov_base: &mut byte as *mut u8 as *mut libc::c_void,
+            iov_len: 1,
+        };
+        let mut control = [0u64; 4];
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+        let received = unsafe {
+            libc::recvmsg(
+                self.socket.as_raw_fd(),
+                &mut msg,
+                libc::MSG_DONTWAIT | libc::MSG_CMSG_CLOEXEC,
+            )
+        };
+        if received < 0 {
+            return;
+        }
+
+        let pidfd = unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            if cmsg.is_null() || (*cmsg).cmsg_type != libc::SCM_RIGHTS {
+                return;
+            }
+            let fd = (libc::CMSG_DATA(cmsg) as *const libc::c_int).read_unaligned();
+            OwnedFd::from_raw_fd(fd)
+        };
+
+        // The pid is -1 once the program is gone
+        self.program_pid = fs::read_to_string(format!("/proc/self/fdinfo/{}", pidfd.as_raw_fd()))
+            .ok()
+            .and_then(|info| {
+                info.lines()
+                    .find_map(|line| line.strip_prefix("Pid:")?.trim().parse().ok())
+            })
+            .filter(|pid| *pid > 0)
+            .map(Pid::from_raw);
+        self.program_pidfd = Some(PidFd::from(pidfd));
+    }
+
+    /// Pid of the program in the PID namespace of the judge
+    pub fn program_pid(&self) -> Option<Pid> {
+        self.program_pid
+    }
+
+    pub fn take_program_pidfd(&mut self) -> Option<PidFd> {
+        self.program_pidfd.take()
+    }
+}
+
+/// Make the process spawned by `command` enter the sandbox before exec, in
+/// `work_dir` if given
+pub fn sandbox(
+    command: &mut Command,
+    config: &SandboxConfig,
+    work_dir: Option<&Path>,
+) -> io::Result<Supervisor> {
+    let mut sockets = [0; 2];
+    unsafe {
+        check(libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+            0,
+            sockets.as_mut_ptr(),
+        ))?;
+    }
+    let [socket, init_socket] = sockets.map(|fd| unsafe { OwnedFd::from_raw_fd(fd) });
+
+    let mut prepared = prepare(config, work_dir, init_socket)?;
+
+    unsafe {
+        command.pre_exec(move || enter(&mut prepared));
+    }
+
+    Ok(Supervisor {
+        socket,
+        program_pid: None,
+        program_pidfd: None,
+    })
+}
+
+fn prepare(
+    config: &SandboxConfig,
+    work_dir: Option<&Path>,
+    socket: OwnedFd,
+) -> io::Result<Prepared> {
+    let root = config.root.canonicalize()?;
+
+    let work_mount = work_dir.map(|work_dir| BindMount {
+        source: work_dir.to_path_buf(),
+        target: config.work_dir.clone(),
+        writable: true,
+    });
+    let cwd = match work_mount {
+        Some(_) => path_cstring(&config.work_dir)?,
+        None => CString::from(c"/"),
+    };
+
+    let mut mounts = Vec::new();
+    for mount in config.mounts.iter().chain(&work_mount) {
+        let target = root.join(mount.target.strip_prefix("/").unwrap_or(&mount.target));
+        let source = mount.source.canonicalize()?;
+        let read_only_flags = if mount.writable {
+            None
+        } else {
+            Some(locked_flags(&source)?)
+        };
+
+        mounts.push((
+            path_cstring(&source)?,
+            path_cstring(&target)?,
+            read_only_flags,
+        ));
+    }
+
+    let proc_dir = root.join("proc");
+    let proc_dir = match proc_dir.is_dir() {
+        true => Some(path_cstring(&proc_dir)?),
+        false => None,
+    };
+
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+
+    Ok(Prepared {
+        root_flags: locked_flags(&root)?,
+        root: path_cstring(&root)?,
+        mounts,
+        cwd,
+        uid_map: format!("{} {} 1", uid, uid),
+        gid_map: format!("{} {} 1", gid, gid),
+        filter: config.filter_syscalls.then(seccomp_filter).transpose()?,
+        proc_dir,
+        socket,
+    })
+}
+
+fn path_cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Path contains a null byte"))
+}
+
+/// Mount flags that have to be kept when remounting the mount of `path`
+/// inside a user namespace
+fn locked_flags(path: &Path) -> io::Result<libc::c_ulong> {
+    let path = path_cstring(path)?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut flags = 0;
+    for (st_flag, ms_flag) in [
+        (libc::ST_NOSUID, libc::MS_NOSUID),
+        (libc::ST_NODEV, libc::MS_NODEV),
+        (libc::ST_NOEXEC, libc::MS_NOEXEC),
+        (libc::ST_NOATIME, libc::MS_NOATIME),
+        (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+        (libc::ST_RELATIME, libc::MS_RELATIME),
+    ] {
+        if stat.f_flag & st_flag != 0 {
+            flags |= ms_flag;
+        }
+    }
+
+    Ok(flags)
+}
+
+/// Turn the return value of a libc call into a `Result`
+fn check(res: libc::c_int) -> io::Result<()> {
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn write_file(path: &CStr, content: &str) -> io::Result<()> {
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY);
+        check(fd)?;
+
+        let written = libc::write(fd, content.as_ptr() as *const libc::c_void, content.len());
+        let err = io::Error::last_os_error();
+        libc::close(fd);
+
+        if written != content.len() as isize {
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Bind mount `source` on `target`, remounting it read-only with the given
+/// flags if any
+fn bind_mount(source: &CString, target: &CString, flags: Option<libc::c_ulong>) -> io::Result<()> {
+    unsafe {
+        check(libc::mount(
+            source.as_ptr(),
+            target.as_ptr(),
+            std::ptr::null(),
+            libc::MS_BIND | libc::MS_REC,
+            std::ptr::null(),
+        ))?;
+
+        if let Some(flags) = flags {
+            check(libc::mount(
+                std::ptr::null(),
+                target.as_ptr(),
+                std::ptr::null(),
+                libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY | flags,
+                std::ptr::null(),
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+/// Enter the sandbox, this runs in the child between fork and exec so only
+/// async-signal-safe calls are allowed
+///
+/// Only the children of a process are in the PID namespace it unshares, so
+/// this process stays outside and forwards the exit status of the program.
+/// Its child is the init of the namespace, which runs the program as its own
+/// child and takes whatever the program leaves behind down with it.
+fn enter(prepared: &mut Prepared) -> io::Result<()> {
+    unsafe {
+        check(libc::unshare(
+            libc::CLONE_NEWUSER
+                | libc::CLONE_NEWNS
+                | libc::CLONE_NEWNET
+                | libc::CLONE_NEWIPC
+                | libc::CLONE_NEWUTS
+                | libc::CLONE_NEWPID,
+        ))?;
+    }
+
+    // Keep the same ids inside the user namespace
+    write_file(c"/proc/self/setgroups", "deny")?;
+    write_file(c"/proc/self/uid_map", &prepared.uid_map)?;
+    write_file(c"/proc/self/gid_map", &prepared.gid_map)?;
+
+    unsafe {
+        let mut status_pipe = [0; 2];
+        check(libc::pipe2(status_pipe.as_mut_ptr(), libc::O_CLOEXEC))?;
+
+        let init = libc::fork();
+        check(init)?;
+        if init > 0 {
+            supervise(init, status_pipe[0]);
+        }
+        libc::close(status_pipe[0]);
+
+        // Nothing would stop the namespace if the supervisor died before this
+        check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0))?;
+        let mut poll_fd = libc::pollfd {
+            fd: status_pipe[1],
+            events: 0,
+            revents: 0,
+        };
+        if libc::poll(&mut poll_fd, 1, 0) > 0 && poll_fd.revents & libc::POLLERR != 0 {
+            libc::_exit(1);
+        }
+
+        isolate(prepared)?;
+
+        let program = libc::fork();
+        check(program)?;
+        if program > 0 {
+            run_init(program, prepared.socket.as_raw_fd(), status_pipe[1]);
+        }
+    }
+
+    if let Some(filter) = &mut prepared.filter {
+        unsafe {
+            filter.set_pid(libc::getpid());
+            let prog = sock_fprog {
+                len: filter.program.len() as libc::c_ushort,
+                filter: filter.program.as_ptr() as *mut sock_filter,
+            };
+            check(libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &prog as *const sock_fprog,
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Set up the mounts, root and capabilities of the namespace, which the init
+/// and the program share
+fn isolate(prepared: &Prepared) -> io::Result<()> {
+    unsafe {
+        // Don't propagate the mounts below to the host
+        check(libc::mount(
+            std::ptr::null(),
+            c"/".as_ptr(),
+            std::ptr::null(),
+            libc::MS_REC | libc::MS_PRIVATE,
+            std::ptr::null(),
+        ))?;
+
+        // The root has to be a mount point to remount it read-only, and the
+        // bind mounts go inside it before that
+        check(libc::mount(
+            prepared.root.as_ptr(),
+            prepared.root.as_ptr(),
+            std::ptr::null(),
+            libc::MS_BIND | libc::MS_REC,
+            std::ptr::null(),
+        ))?;
+    }
+
+    for (source, target, flags) in &prepared.mounts {
+        bind_mount(source, target, *flags)?;
+    }
+
+    unsafe {
+        // Only the processes of the namespace show up in its own `/proc`
+        if let Some(proc_dir) = &prepared.proc_dir {
+            check(libc::mount(
+                c"proc".as_ptr(),
+                proc_dir.as_ptr(),
+                c"proc".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                std::ptr::null(),
+            ))?;
+        }
+
+        check(libc::mount(
+            std::ptr::null(),
+            prepared.root.as_ptr(),
+            std::ptr::null(),
+            libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY | prepared.root_flags,
+            std::ptr::null(),
+        ))?;
+
+        check(libc::chdir(prepared.root.as_ptr()))?;
+        check(libc::chroot(c".".as_ptr()))?;
+        check(libc::chdir(prepared.cwd.as_ptr()))?;
+
+        // Drop every capability, so neither the program nor what it runs can
+        // get them back
+        for cap in 0..64 {
+            libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0);
+        }
+        libc::prctl(
+            libc::PR_CAP_AMBIENT,
+            libc::PR_CAP_AMBIENT_CLEAR_ALL,
+            0,
+            0,
+            0,
+        );
+
+        let mut header = CapHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let data = [
+            CapData {
+                effective: 0,
+                permitted: 0,
+                inheritable: 0,
+            },
+            CapData {
+                effective: 0,
+                permitted: 0,
+                inheritable: 0,
+            },
+        ];
+        check(libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) as libc::c_int)?;
+
+        check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+    }
+
+    Ok(())
+}
+
+fn errno() -> Option<i32> {
+    io::Error::last_os_error().raw_os_error()
+}
+
+/// Close every file descriptor but `keep`
+unsafe fn close_other_fds(keep: libc::c_int) {
+    let keep = keep as libc::c_uint;
+    if keep > 0 {
+        close_range(0, keep - 1);
+    }
+    close_range(keep + 1, libc::c_uint::MAX);
+}
+
+unsafe fn close_range(first: libc::c_uint, last: libc::c_uint) {
+    if libc::syscall(libc::SYS_close_range, first, last, 0) == 0 {
+        return;
+    }
+
+    // close_range is only available since Linux 5.9
+    let mut limit: libc::rlimit = std::mem::zeroed();
+    libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit);
+    let last = limit.rlim_cur.min(last as libc::rlim_t).min(1 << 16) as libc::c_uint;
+    for fd in first..=last {
+        libc::close(fd as libc::c_int);
+    }
+}
+
+/// Wait for the init, then exit the way the program did. The judge only sees
+/// this process.
+unsafe fn supervise(init: libc::pid_t, status_fd: libc::c_int) -> ! {
+    // The spawn only returns once every process closed its copy of the pipe
+    // reporting exec errors
+    close_other_fds(status_fd);
+
+    let mut init_status = 0;
+    while libc::waitpid(init, &mut init_status, 0) < 0 && errno() == Some(libc::EINTR) {}
+
+    let mut status: libc::c_int = 0;
+    let size = std::mem::size_of::<libc::c_int>();
+    let read = libc::read(status_fd, &mut status as *mut _ as *mut libc::c_void, size);
+    // The init failed before running the program
+    if read != size as isize {
+        status = init_status;
+    }
+
+    let signal = if libc::WIFSIGNALED(status) {
+        libc::WTERMSIG(status)
+    } else if libc::WIFSTOPPED(status) {
+        libc::WSTOPSIG(status)
+    } else {
+        libc::_exit(libc::WEXITSTATUS(status));
+    };
+
+    // Get the same signal, without dumping a core
+    let no_core = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+    libc::signal(signal, libc::SIG_DFL);
+    let mut set: libc::sigset_t = std::mem::zeroed();
+    libc::sigemptyset(&mut set);
+    libc::sigaddset(&mut set, signal);
+    libc::sigprocmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+    libc::raise(signal);
+
+    libc::_exit(128 + signal)
+}
+
+/// Send the program to the judge, then reap everything until the program
+/// exits and report its status to the supervisor. The namespace is killed
+/// when the init exits.
+unsafe fn run_init(program: libc::pid_t, socket: libc::c_int, status_fd: libc::c_int) -> ! {
+    send_pidfd(socket, program);
+    close_other_fds(status_fd);
+
+    let mut status = 0;
+    loop {
+        let pid = libc::waitpid(-1, &mut status, libc::WUNTRACED);
+        if pid == program {
+            break;
+        }
+        if pid < 0 && errno() != Some(libc::EINTR) {
+            libc::_exit(1);
+        }
+    }
+
+    libc::write(
+        status_fd,
+        &status as *const _ as *const libc::c_void,
+        std::mem::size_of::<libc::c_int>(),
+    );
+    libc::_exit(0)
+}
+
+/// Send a pidfd of the program over `socket`, or an empty message if it
+/// can't be opened so the judge doesn't wait for it
+unsafe fn send_pidfd(socket: libc::c_int, program: libc::pid_t) {
+    let pidfd = libc::syscall(libc::SYS_pidfd_open, program, 0) as libc::c_int;
+
+    let mut byte = 0u8;
+    let mut iov = libc::iovec {
+        iov_base: &mut byte as *mut u8 as *mut libc::c_void,
+        iov_len: 1,
+    };
+    // Room for a single file descriptor
+    let mut control = [0u64; 4];
+    let mut msg: libc::msghdr = std::mem::zeroed();
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+
+    if pidfd >= 0 {
+        let fd_size = std::mem::size_of::<libc::c_int>() as libc::c_uint;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = libc::CMSG_SPACE(fd_size) as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fd_size) as _;
+        (libc::CMSG_DATA(cmsg) as *mut libc::c_int).write_unaligned(pidfd);
+    }
+
+    libc::sendmsg(socket, &msg, libc::MSG_NOSIGNAL);
+}
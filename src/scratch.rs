@@ -0,0 +1,91 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::checker::temp_file_path;
+
+/// Working directory given to each run of the judged program
+#[derive(Debug, Clone, Default)]
+pub struct ScratchConfig {
+    /// Mount a tmpfs of this size in Mb on each working directory, so what the
+    /// program writes can't fill the disk of the judge. Mounting needs
+    /// `CAP_SYS_ADMIN`.
+    pub tmpfs_size: Option<u64>,
+    /// Leave behind the working directory of runs that aren't accepted, to
+    /// look at the files they wrote. Its tmpfs stays mounted.
+    pub keep_failed: bool,
+}
+
+/// Fresh working directory of a run, removed when dropped unless kept
+#[derive(Debug)]
+pub(crate) struct WorkDir {
+    path: PathBuf,
+    tmpfs: bool,
+    keep: bool,
+}
+
+impl WorkDir {
+    pub fn new(config: &ScratchConfig) -> io::Result<Self> {
+        let path = PathBuf::from(temp_file_path("run"));
+        fs::create_dir(&path)?;
+
+        let mut work_dir = Self {
+            path,
+            tmpfs: false,
+            keep: false,
+        };
+        if let Some(size) = config.tmpfs_size {
+            mount_tmpfs(&work_dir.path, size)?;
+            work_dir.tmpfs = true;
+        }
+
+        Ok(work_dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Don't remove the directory when dropped, returning its path
+    pub fn keep(&mut self) -> &Path {
+        self.keep = true;
+        &self.path
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+
+        if self.tmpfs {
+            if let Ok(path) = CString::new(self.path.as_os_str().as_bytes()) {
+                unsafe { libc::umount2(path.as_ptr(), libc::MNT_DETACH) };
+            }
+        }
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn mount_tmpfs(path: &Path, size: u64) -> io::Result<()> {
+    let target = CString::new(path.as_os_str().as_bytes())?;
+    let options = CString::new(format!("size={}m,mode=0700", size))?;
+
+    let res = unsafe {
+        libc::mount(
+            c"tmpfs".as_ptr(),
+            target.as_ptr(),
+            c"tmpfs".as_ptr(),
+            libc::MS_NOSUID | libc::MS_NODEV,
+            options.as_ptr().cast(),
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use std::io;
+
+use libc::sock_filter;
+
+/// Architecture the filter is written for, the filter is only built for the
+/// ones whose syscalls are listed below
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xC000_003E);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xC000_00B7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[];
+
+/// Syscalls a regular single process program may need
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_readv,
+    libc::SYS_writev,
+    libc::SYS_pread64,
+    libc::SYS_pwrite64,
+    libc::SYS_lseek,
+    libc::SYS_close,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_openat,
+    libc::SYS_faccessat,
+    libc::SYS_readlinkat,
+    libc::SYS_getdents64,
+    libc::SYS_getcwd,
+    libc::SYS_ioctl,
+    libc::SYS_fcntl,
+    libc::SYS_dup,
+    libc::SYS_dup3,
+    libc::SYS_ppoll,
+    libc::SYS_pselect6,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_brk,
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_futex,
+    libc::SYS_set_tid_address,
+    libc::SYS_set_robust_list,
+    libc::SYS_get_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_nanosleep,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_getres,
+    libc::SYS_gettimeofday,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_getppid,
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    libc::SYS_getrandom,
+    libc::SYS_getrlimit,
+    libc::SYS_prlimit64,
+    libc::SYS_getrusage,
+    libc::SYS_times,
+    libc::SYS_sysinfo,
+    libc::SYS_uname,
+    libc::SYS_execve,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_open,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_stat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_lstat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_access,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_readlink,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_getdents,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_dup2,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_select,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_time,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_arch_prctl,
+];
+
+const fn stmt(code: u32, k: u32) -> sock_filter {
+    sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+const fn jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+/// Offsets in `struct seccomp_data`
+const SYSCALL_NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+const FIRST_ARG_OFFSET: u32 = 16;
+
+/// Seccomp BPF program allowing only `ALLOWED_SYSCALLS`, threads but no new
+/// processes, and signals only to the process itself
+pub struct Filter {
+    pub program: Vec<sock_filter>,
+    /// Instruction comparing with the pid of the process, only known after
+    /// forking
+    pid_index: usize,
+}
+
+impl Filter {
+    /// Set the pid of the process the filter is for, this doesn't allocate so
+    /// it can be done between fork and exec
+    pub fn set_pid(&mut self, pid: libc::pid_t) {
+        self.program[self.pid_index].k = pid as u32;
+    }
+}
+
+pub fn seccomp_filter() -> io::Result<Filter> {
+    use libc::{BPF_ABS, BPF_JEQ, BPF_JMP, BPF_JSET, BPF_K, BPF_LD, BPF_RET, BPF_W};
+
+    let arch = AUDIT_ARCH.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "The sandbox is not supported on this architecture",
+        )
+    })?;
+
+    let kill = stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS);
+    let allow = stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW);
+
+    let mut filter = vec![
+        stmt(BPF_LD | BPF_W | BPF_ABS, ARCH_OFFSET),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, arch, 1, 0),
+        kill,
+        stmt(BPF_LD | BPF_W | BPF_ABS, SYSCALL_NR_OFFSET),
+    ];
+
+    // x32 syscalls share the architecture with x86_64
+    #[cfg(target_arch = "x86_64")]
+    filter.extend([
+        jump(BPF_JMP | libc::BPF_JGE | BPF_K, 0x4000_0000, 0, 1),
+        kill,
+    ]);
+
+    for &syscall in ALLOWED_SYSCALLS {
+        filter.extend([jump(BPF_JMP | BPF_JEQ | BPF_K, syscall as u32, 0, 1), allow]);
+    }
+
+    // Signals only go to the process itself, not to the init of its PID
+    // namespace
+    filter.extend([
+        jump(BPF_JMP | BPF_JEQ | BPF_K, libc::SYS_tgkill as u32, 0, 4),
+        stmt(BPF_LD | BPF_W | BPF_ABS, FIRST_ARG_OFFSET),
+    ]);
+    let pid_index = filter.len();
+    filter.extend([jump(BPF_JMP | BPF_JEQ | BPF_K, 0, 0, 1), allow, kill]);
+
+    // The flags of clone3 can't be inspected, with ENOSYS the libc falls back
+    // to clone
+    filter.extend([
+        jump(BPF_JMP | BPF_JEQ | BPF_K, libc::SYS_clone3 as u32, 0, 1),
+        stmt(
+            BPF_RET | BPF_K,
+            libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32,
+        ),
+    ]);
+
+    // clone is only allowed for threads, this has to be the last check since
+    // it overwrites the syscall number
+    filter.extend([
+        jump(BPF_JMP | BPF_JEQ | BPF_K, libc::SYS_clone as u32, 0, 3),
+        stmt(BPF_LD | BPF_W | BPF_ABS, FIRST_ARG_OFFSET),
+        jump(BPF_JMP | BPF_JSET | BPF_K, libc::CLONE_THREAD as u32, 0, 1),
+        allow,
+        kill,
+    ]);
+
+    Ok(Filter {
+        program: filter,
+        pid_index,
+    })
+}
@@ -0,0 +1,424 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufReader};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixListener;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::bin_utils::{judge_directory_with_checker, RunConfig};
+use crate::ipc::*;
+use crate::jobs::{Job, JobQueue, JobStatus};
+use crate::language::{BuildDir, Compilation};
+use crate::problem::{Problem, ProblemCatalog};
+use crate::utils::{base64_decode, sorted_list_numbers_in_folder, EnvPolicy, ProblemVerdict};
+
+/// Time between checks of a followed job that isn't progressing
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Maximum amount of clients handled at the same time, the next ones wait to
+/// be accepted until one leaves
+pub const MAX_CONNECTIONS: usize = 256;
+
+/// Time a client can go without sending anything, or without reading what
+/// it's sent, before it's disconnected
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Bound on the connections handled at the same time
+pub(crate) struct ConnectionLimit {
+    count: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// Room taken by a connection, given back when dropped
+pub(crate) struct ConnectionSlot<'a>(&'a ConnectionLimit);
+
+impl ConnectionLimit {
+    pub fn new() -> Self {
+        Self {
+            count: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Wait until there's room for another connection
+    pub fn acquire(&self) -> ConnectionSlot<'_> {
+        let count = self.count.lock().unwrap_or_else(|err| err.into_inner());
+        let mut count = self
+            .freed
+            .wait_while(count, |count| *count >= MAX_CONNECTIONS)
+            .unwrap_or_else(|err| err.into_inner());
+        *count += 1;
+
+        ConnectionSlot(self)
+    }
+}
+
+impl Drop for ConnectionSlot<'_> {
+    fn drop(&mut self) {
+        *self.0.count.lock().unwrap_or_else(|err| err.into_inner()) -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// A request that couldn't be judged
+#[derive(Debug)]
+pub struct RequestError {
+    /// What to tell the client
+    pub msg: &'static str,
+    pub source: Box<dyn Error>,
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.msg, self.source)
+    }
+}
+
+impl Error for RequestError {}
+
+impl RequestError {
+    fn new(msg: &'static str, source: impl Into<Box<dyn Error>>) -> Self {
+        Self {
+            msg,
+            source: source.into(),
+        }
+    }
+}
+
+/// Everything a judge server needs to handle requests
+#[derive(Debug, Clone, Default)]
+pub struct ServerConfig {
+    pub run: RunConfig,
+    /// Problems requests refer to by id. When given, requests can't refer to
+    /// paths on the judge and the limits are the ones of the problem.
+    pub catalog: Option<ProblemCatalog>,
+}
+
+/// Amount of concurrent judgments, at most one per CPU core so they don't
+/// compete for CPU time and get unfair timings
+pub fn worker_count(requested: Option<usize>) -> usize {
+    let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+
+    requested.unwrap_or(cores).clamp(1, cores)
+}
+
+/// Bind a Unix domain socket, only accessible with the given permissions
+pub fn bind_unix(path: &str, mode: u32) -> io::Result<UnixListener> {
+    // Remove the socket left behind by a previous run
+    if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+
+    // Nobody can connect until the permissions are set
+    let old_mask = unsafe { libc::umask(0o777) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(old_mask) };
+
+    let listener = listener?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+
+    Ok(listener)
+}
+
+/// Judge a request, calling `on_test_case` before running each test case.
+/// Every front-end of the judge goes through here.
+pub fn judge_request(
+    request: JudeRequest,
+    config: &ServerConfig,
+    mut on_test_case: impl FnMut(u32),
+) -> Result<JudgeResponse, RequestError> {
+    let request_limits = request.limits();
+    let JudeRequest {
+        cmd,
+        cmd_args,
+        source: code,
+        language,
+        binary,
+        test_dir,
+        problem: problem_id,
+        run_all,
+        env,
+        ..
+    } = request;
+
+    let request_env = EnvPolicy {
+        set: env.unwrap_or_default(),
+        ..EnvPolicy::default()
+    };
+    request_env
+        .validate()
+        .map_err(|err| RequestError::new("Invalid environment", err))?;
+
+    // With a catalog the client can't point the judge at arbitrary paths
+    if config.catalog.is_some() && (cmd.is_some() || test_dir.is_some()) {
+        return Err(RequestError::new(
+            "Paths on the judge are not allowed, use problem ids and send the program",
+            "Request with paths",
+        ));
+    }
+
+    let (directory, problem, limits, numbers) = match (test_dir, problem_id, &config.catalog) {
+        (Some(test_dir), None, _) => {
+            let problem = Problem::load(&test_dir)
+                .map_err(|err| RequestError::new("Invalid problem descriptor", err))?;
+            let limits = problem
+                .as_ref()
+                .and_then(|problem| problem.limits)
+                .map(|limits| limits.limits())
+                .or(request_limits)
+                .ok_or_else(|| {
+                    RequestError::new("Expected time and memory limits", "Request without limits")
+                })?;
+
+            let mut numbers = sorted_list_numbers_in_folder(&test_dir)
+                .map_err(|err| RequestError::new("Can't open directory", err))?;
+            if let Some(problem) = &problem {
+                numbers = problem.test_cases(&numbers);
+            }
+
+            (test_dir, problem, limits, numbers)
+        }
+        (None, Some(id), Some(catalog)) => {
+            let entry = catalog.get(&id).ok_or_else(|| {
+                RequestError::new("Unknown problem", format!("Unknown problem {:?}", id))
+            })?;
+
+            (
+                entry.directory.clone(),
+                Some(entry.problem.clone()),
+                entry.limits,
+                entry.test_cases.clone(),
+            )
+        }
+        _ => {
+            return Err(RequestError::new(
+                "Expected either test_dir or a problem id",
+                "Invalid test cases in request",
+            ))
+        }
+    };
+    let checker = problem
+        .as_ref()
+        .map(|problem| problem.checker.clone())
+        .unwrap_or_default();
+
+    // The problem and then the request add to the environment of the judge
+    let mut run_config = config.run.clone();
+    if let Some(problem) = &problem {
+        run_config.io = problem.io.clone();
+        run_config.env.merge(&problem.env);
+    }
+    run_config.env.merge(&request_env);
+    // Every test case is needed to score the groups
+    let problem = problem.filter(Problem::is_scored);
+    let run_all = run_all.unwrap_or(false) || problem.is_some();
+
+    // Holds the submitted files until the judgment is over
+    let build_dir;
+    let (cmd, cmd_args) = match (cmd, code, language, binary) {
+        (Some(cmd), None, None, None) => (cmd, cmd_args),
+        (None, Some(code), Some(language), None) => {
+            let profile = config.run.language(&language).ok_or_else(|| {
+                RequestError::new(
+                    "Unknown language",
+                    format!("Unknown language {:?}", language),
+                )
+            })?;
+            build_dir =
+                BuildDir::new().map_err(|err| RequestError::new("Couldn't compile", err))?;
+
+            let compilation = profile
+                .compile(&code, build_dir.path(), &config.run)
+                .map_err(|err| RequestError::new("Couldn't compile", err))?;
+            build_dir.mount(&mut run_config);
+            match compilation {
+                Compilation::Success(program) => (program.cmd, Some(program.args)),
+                Compilation::Error(log) => {
+                    return Ok(JudgeResponse::ok(ProblemVerdict::CompilationError { log }))
+                }
+            }
+        }
+        (None, None, None, Some(binary)) => {
+            let bytes =
+                base64_decode(&binary).map_err(|err| RequestError::new("Invalid binary", err))?;
+            build_dir = BuildDir::new()
+                .map_err(|err| RequestError::new("Couldn't store the binary", err))?;
+
+            build_dir
+                .write_executable(&bytes)
+                .map_err(|err| RequestError::new("Couldn't store the binary", err))?;
+            let exe = build_dir.mount(&mut run_config).join("main");
+            (exe.to_string_lossy().into_owned(), cmd_args)
+        }
+        _ => {
+            return Err(RequestError::new(
+                "Expected either cmd, source and language, or binary",
+                "Invalid program in request",
+            ))
+        }
+    };
+
+    let test_cases = numbers.into_iter().inspect(|num| on_test_case(*num));
+
+    let report = judge_directory_with_checker(
+        &cmd,
+        cmd_args,
+        &directory,
+        test_cases,
+        limits,
+        &run_config,
+        &checker,
+        run_all,
+        |_| {},
+    )
+    .map_err(|err| RequestError::new("Error while judging, check checker's log", err))?;
+    let score = problem.map(|problem| problem.score(&report));
+
+    Ok(JudgeResponse::report(report, score))
+}
+
+/// Accept connections forever, handling at most `MAX_CONNECTIONS` at once and
+/// judging their requests on `workers` threads
+pub fn serve(
+    incoming: impl Iterator<Item = io::Result<Connection>>,
+    queue: &JobQueue,
+    workers: usize,
+    config: &ServerConfig,
+) {
+    let limit = ConnectionLimit::new();
+    let mut incoming = incoming;
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| queue.work(config));
+        }
+
+        loop {
+            let slot = limit.acquire();
+            let mut stream = match incoming.next() {
+                Some(Ok(stream)) => stream,
+                Some(Err(e)) => {
+                    eprintln!("Couldn't get client {e}");
+                    continue;
+                }
+                None => break,
+            };
+
+            scope.spawn(move || {
+                let _slot = slot;
+                if let Err(err) = handle_client(&mut stream, queue, config) {
+                    eprintln!("Error handling client {}: {err}", stream.peer());
+                }
+            });
+        }
+    });
+}
+
+/// Answer the messages of a client until it closes the connection or stays
+/// silent for `CLIENT_TIMEOUT`, errors are reported to the client before
+/// being returned
+fn handle_client(
+    stream: &mut Connection,
+    queue: &JobQueue,
+    config: &ServerConfig,
+) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    loop {
+        let message: ClientMessage = match read_message(&mut reader) {
+            Ok(Some(message)) => message,
+            Ok(None) => return Ok(()),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Err("Timed out waiting for a request".into());
+            }
+            Err(err) => {
+                send(stream, &JudgeResponse::error("Invalid judge request"))?;
+                return Err(err.into());
+            }
+        };
+
+        if message.version() != PROTOCOL_VERSION {
+            send(
+                stream,
+                &JudgeResponse::error("Unsupported protocol version"),
+            )?;
+            return Err(format!("Unsupported protocol version {}", message.version()).into());
+        }
+
+        // The job goes on even if the client stops listening
+        match message {
+            ClientMessage::Judge(request) => follow_job(stream, queue, queue.submit(*request))?,
+            ClientMessage::Query(query) => follow_job(stream, queue, query.job)?,
+            ClientMessage::List(ListQuery {
+                list: ListTarget::Problems,
+                ..
+            }) => match &config.catalog {
+                Some(catalog) => send(stream, &JudgeResponse::problems(catalog.list()))?,
+                None => send(
+                    stream,
+                    &JudgeResponse::error("The judge has no problem catalog"),
+                )?,
+            },
+        }
+    }
+}
+
+/// Send the whole progress of a job until it's done
+fn follow_job(stream: &mut Connection, queue: &JobQueue, id: u64) -> io::Result<()> {
+    let Some(mut job) = queue.get(id) else {
+        return send(stream, &JudgeResponse::error("Unknown job"));
+    };
+
+    send(stream, &JudgeResponse::job(id))?;
+
+    let mut sent = 0;
+    loop {
+        for test_case in &job.test_cases[sent..] {
+            send(stream, &JudgeResponse::test_case(*test_case))?;
+        }
+        sent = job.test_cases.len();
+
+        match job {
+            Job {
+                status: JobStatus::Finished,
+                report: Some(report),
+                score,
+                ..
+            } => return send(stream, &JudgeResponse::report(report, score)),
+            // Judged without running the test cases, e.g. compilation errors
+            Job {
+                status: JobStatus::Finished,
+                verdict: Some(verdict),
+                ..
+            } => return send(stream, &JudgeResponse::ok(verdict)),
+            Job {
+                status: JobStatus::Failed,
+                error,
+                ..
+            } => {
+                let error = error.unwrap_or_else(|| String::from("Unknown error"));
+                return send(stream, &JudgeResponse::error(&error));
+            }
+            _ => {}
+        }
+
+        job = match queue.wait_update(&job, FOLLOW_INTERVAL) {
+            Some(job) => job,
+            None => return send(stream, &JudgeResponse::error("Unknown job")),
+        };
+    }
+}
+
+fn send(stream: &mut Connection, response: &JudgeResponse) -> io::Result<()> {
+    write_message(stream, response)
+}
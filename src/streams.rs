@@ -0,0 +1,94 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process::ChildStdin;
+use std::thread::{self, JoinHandle};
+
+/// Write the contents of the file at `input_path` into the stdin of a process
+/// in the background, closing it when everything is written
+///
+/// The process may exit without reading the whole input, so a broken pipe is
+/// not considered an error.
+pub fn feed(mut stdin: ChildStdin, input_path: &str) -> io::Result<JoinHandle<io::Result<()>>> {
+    let mut input = File::open(input_path)?;
+
+    Ok(thread::spawn(move || {
+        match io::copy(&mut input, &mut stdin) {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            Err(err) => Err(err),
+        }
+        // stdin is dropped here, the process reads EOF
+    }))
+}
+
+/// Copy everything read from `reader` into the file at `spool_path` in the
+/// background, returning the amount of bytes copied
+///
+/// When more than `limit` bytes are read (0 means no limit) it stops copying,
+/// calls `on_limit` and returns `None`.
+pub fn spool<R, F>(
+    mut reader: R,
+    spool_path: &str,
+    limit: u64,
+    on_limit: F,
+) -> io::Result<JoinHandle<io::Result<Option<u64>>>>
+where
+    R: Read + Send + 'static,
+    F: FnOnce() + Send + 'static,
+{
+    let mut spool_file = File::create(spool_path)?;
+
+    Ok(thread::spawn(move || {
+        let mut buf = [0; 64 * 1024];
+        let mut copied: u64 = 0;
+
+        loop {
+            let bytes_read = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(bytes_read) => bytes_read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+
+            copied += bytes_read as u64;
+            if limit > 0 && copied > limit {
+                // Closing the pipe also stops the process on its next write
+                drop(reader);
+                on_limit();
+                return Ok(None);
+            }
+
+            spool_file.write_all(&buf[..bytes_read])?;
+        }
+
+        spool_file.flush()?;
+        Ok(Some(copied))
+    }))
+}
+
+/// Read everything from `reader` in the background, keeping only the first
+/// `limit` bytes and discarding the rest so the process never blocks on a full
+/// pipe
+pub fn capture<R>(mut reader: R, limit: usize) -> JoinHandle<io::Result<Vec<u8>>>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut captured = Vec::new();
+        let mut buf = [0; 64 * 1024];
+
+        loop {
+            let bytes_read = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(bytes_read) => bytes_read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+
+            let kept = bytes_read.min(limit - captured.len());
+            captured.extend_from_slice(&buf[..kept]);
+        }
+
+        Ok(captured)
+    })
+}
@@ -1,14 +1,190 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Default output limit in Mb
+pub const DEFAULT_OUTPUT_LIMIT: u64 = 64;
+
+/// Default wall time limit in ms for the given CPU time limit
+pub fn default_wall_time(time: u64) -> u64 {
+    time.saturating_mul(3)
+}
+
+/// Resource limits of a run, a limit of 0 means unlimited
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Limits {
+    /// CPU time limit in ms
+    pub time: u64,
+    /// Wall time limit in ms
+    pub wall_time: u64,
+    /// Memory limit in Mb
+    pub memory: u64,
+    /// Output limit in Mb
+    pub output: u64,
+}
+
+/// Environment of the judged program, which doesn't inherit the one of the
+/// judge so runs are the same on every machine
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EnvPolicy {
+    /// Variables of the judge passed through to the program
+    #[serde(default)]
+    pub pass: Vec<String>,
+    /// Variables set for the program, e.g. `ONLINE_JUDGE=1`
+    #[serde(default)]
+    pub set: BTreeMap<String, String>,
+}
+
+impl EnvPolicy {
+    /// Add the variables of another policy, its values win
+    pub fn merge(&mut self, other: &EnvPolicy) {
+        self.pass.extend(other.pass.iter().cloned());
+        self.set.extend(
+            other
+                .set
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let mut names = self.pass.iter().chain(self.set.keys());
+        if let Some(name) = names.find(|name| name.is_empty() || name.contains(['=', '\0'])) {
+            return Err(format!("Invalid environment variable name {:?}", name));
+        }
+        if self.set.values().any(|value| value.contains('\0')) {
+            return Err(String::from("Environment variable value with a null byte"));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ProblemVerdict {
-    Accepted { time: u64, memory: u64 },
-    WrongAnswer { msg: String },
+    Accepted {
+        cpu_time: u64,
+        wall_time: u64,
+        memory: u64,
+    },
+    WrongAnswer {
+        msg: String,
+    },
+    PresentationError {
+        msg: String,
+    },
+    PartiallyCorrect {
+        points: f64,
+        msg: String,
+    },
     TimeLimitExceeded,
+    WallTimeLimitExceeded,
     MemoryLimitExceeded,
+    OutputLimitExceeded,
     IdleLimitExceeded,
-    RuntimeError(i32),
+    RuntimeError {
+        termination: Termination,
+        /// What the program wrote to stderr, truncated
+        stderr: Option<String>,
+    },
+    /// The program tried something forbidden by the sandbox
+    SecurityViolation,
+    /// The submitted source didn't compile, with the output of the compiler
+    CompilationError {
+        log: String,
+    },
+}
+
+/// How a program that failed at runtime ended
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Termination {
+    /// Exited with a non-zero status
+    ExitCode(i32),
+    /// Killed by a signal, e.g. `SIGSEGV` with "Segmentation fault"
+    Signal {
+        number: i32,
+        name: String,
+        description: String,
+    },
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Termination::ExitCode(code) => write!(f, "exit code {}", code),
+            Termination::Signal {
+                name, description, ..
+            } => write!(f, "{} ({})", name, description),
+        }
+    }
+}
+
+/// Resources used by a run
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Usage {
+    /// CPU time in ms
+    pub cpu_time: u64,
+    /// Wall time in ms
+    pub wall_time: u64,
+    /// Memory in Mb
+    pub memory: u64,
+}
+
+/// Result of judging a single test case
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestResult {
+    pub test_case: u32,
+    pub verdict: ProblemVerdict,
+    pub usage: Usage,
+    /// What the program wrote to stderr, truncated
+    pub stderr: Option<String>,
+}
+
+/// Results of judging several test cases
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestReport {
+    /// Verdict of the first failed test case, or `Accepted` with the maximum
+    /// usage among the test cases if all passed
+    pub verdict: ProblemVerdict,
+    pub failed_test_case: Option<u32>,
+    pub passed: u32,
+    pub total: u32,
+    pub tests: Vec<TestResult>,
+}
+
+impl TestReport {
+    pub fn new(tests: Vec<TestResult>) -> Self {
+        let failed = tests
+            .iter()
+            .find(|test| !matches!(test.verdict, ProblemVerdict::Accepted { .. }));
+
+        let (verdict, failed_test_case) = match failed {
+            Some(test) => (test.verdict.clone(), Some(test.test_case)),
+            None => (
+                ProblemVerdict::Accepted {
+                    cpu_time: tests.iter().map(|t| t.usage.cpu_time).max().unwrap_or(0),
+                    wall_time: tests.iter().map(|t| t.usage.wall_time).max().unwrap_or(0),
+                    memory: tests.iter().map(|t| t.usage.memory).max().unwrap_or(0),
+                },
+                None,
+            ),
+        };
+
+        let passed = tests
+            .iter()
+            .filter(|test| matches!(test.verdict, ProblemVerdict::Accepted { .. }))
+            .count() as u32;
+
+        Self {
+            verdict,
+            failed_test_case,
+            passed,
+            total: tests.len() as u32,
+            tests,
+        }
+    }
 }
 
 pub fn sorted_list_numbers_in_folder(
@@ -35,3 +211,68 @@ pub fn sorted_list_numbers_in_folder(
 
     Ok(numbers)
 }
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode bytes in standard base64 with padding
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let block = chunk.iter().enumerate().fold(0u32, |block, (i, byte)| {
+            block | (*byte as u32) << (16 - 8 * i)
+        });
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (block >> (18 - 6 * i)) & 0x3f;
+                encoded.push(BASE64_ALPHABET[index as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Decode standard base64 with its padding, whitespace is ignored
+pub fn base64_decode(encoded: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+    let mut block: u32 = 0;
+    let mut bits = 0;
+    let mut length = 0;
+    let mut padding = 0;
+
+    for byte in encoded.bytes().filter(|byte| !byte.is_ascii_whitespace()) {
+        length += 1;
+        if byte == b'=' {
+            padding += 1;
+            continue;
+        }
+        if padding > 0 {
+            return Err("Base64 data after the padding".into());
+        }
+
+        let value = BASE64_ALPHABET
+            .iter()
+            .position(|c| *c == byte)
+            .ok_or_else(|| format!("Invalid base64 character {:?}", byte as char))?;
+
+        block = (block << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((block >> bits) as u8);
+        }
+    }
+
+    if length % 4 != 0 || padding > 2 {
+        return Err(
+            format!("Invalid base64 length {length}, with {padding} padding characters").into(),
+        );
+    }
+
+    Ok(decoded)
+}
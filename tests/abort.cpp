@@ -0,0 +1,6 @@
+#include <cstdlib>
+
+// Crashes before reading anything
+int main() {
+    abort();
+}
@@ -0,0 +1,30 @@
+#include <fstream>
+#include <iostream>
+
+using namespace std;
+
+// Testlib-style checker invoked as `checker <input> <output> <answer>`
+int main(int argc, char *argv[]) {
+    if (argc != 4) {
+        cerr << "Usage: checker <input> <output> <answer>";
+        return 3;
+    }
+
+    ifstream out(argv[2]), ans(argv[3]);
+
+    long long expected, found;
+    ans >> expected;
+
+    if (!(out >> found)) {
+        cerr << "Expected an integer";
+        return 2;
+    }
+
+    if (expected != found) {
+        cerr << "Expected " << expected << ", found " << found;
+        return 1;
+    }
+
+    cerr << "Answer is " << found;
+    return 0;
+}
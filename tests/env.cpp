@@ -0,0 +1,11 @@
+#include <cstdlib>
+#include <iostream>
+
+using namespace std;
+
+int main() {
+    const char *online_judge = getenv("ONLINE_JUDGE");
+
+    cout << (online_judge ? online_judge : "unset") << ' ';
+    cout << (getenv("PATH") ? "path" : "no-path") << '\n';
+}
@@ -0,0 +1,18 @@
+#include <cstdio>
+
+int main() {
+    freopen("input.txt", "r", stdin);
+    freopen("output.txt", "w", stdout);
+
+    long long n;
+    scanf("%lld", &n);
+
+    long long a = 0, b = 1;
+    for (long long i = 0; i < n; i++) {
+        long long next = (a + b) % 1000000007;
+        a = b;
+        b = next;
+    }
+
+    printf("%lld\n", a);
+}
@@ -0,0 +1,21 @@
+#include <iostream>
+#include <unistd.h>
+
+using namespace std;
+
+// Start processes that wait forever, telling whether all of them could start
+int main() {
+    for (int i = 0; i < 16; i++) {
+        pid_t pid = fork();
+        if (pid < 0) {
+            cout << "limited" << endl;
+            return 0;
+        }
+        if (pid == 0) {
+            pause();
+            return 0;
+        }
+    }
+
+    cout << "unlimited" << endl;
+}
@@ -0,0 +1,23 @@
+#include <iostream>
+#include <string>
+
+using namespace std;
+
+int main() {
+    long long lo = 1, hi = 1000000000000LL;
+
+    while (true) {
+        long long mid = lo + (hi - lo) / 2;
+        cout << "? " << mid << endl;
+
+        string response;
+        cin >> response;
+
+        if (response == "<") lo = mid + 1;
+        else if (response == ">") hi = mid - 1;
+        else {
+            cout << "! " << mid << endl;
+            return 0;
+        }
+    }
+}
@@ -0,0 +1,42 @@
+#include <fstream>
+#include <iostream>
+
+using namespace std;
+
+// Testlib-style interactor invoked as `interactor <input> <output>`, the
+// program has to guess the number in the input with at most 40 queries
+int main(int argc, char *argv[]) {
+    if (argc != 3) {
+        cerr << "Usage: interactor <input> <output>";
+        return 3;
+    }
+
+    ifstream in(argv[1]);
+    long long secret;
+    in >> secret;
+
+    for (int queries = 0; queries < 40; queries++) {
+        char type;
+        long long guess;
+        if (!(cin >> type >> guess)) {
+            cerr << "Unexpected end of interaction";
+            return 1;
+        }
+
+        if (type == '!') {
+            if (guess == secret) {
+                cerr << "Guessed in " << queries << " queries";
+                return 0;
+            }
+            cerr << "Expected " << secret << ", found " << guess;
+            return 1;
+        }
+
+        if (guess < secret) cout << "<" << endl;
+        else if (guess > secret) cout << ">" << endl;
+        else cout << "=" << endl;
+    }
+
+    cerr << "Too many queries";
+    return 1;
+}
@@ -0,0 +1,13 @@
+#include <iostream>
+#include <unistd.h>
+
+using namespace std;
+
+// Tell whether it's the only child of the init of its own PID namespace
+int main() {
+    if (getpid() == 2 && getppid() == 1) {
+        cout << "isolated" << endl;
+    } else {
+        cout << "shared" << endl;
+    }
+}
@@ -0,0 +1,11 @@
+#include <iomanip>
+#include <iostream>
+
+using namespace std;
+
+int main() {
+    int n;
+    cin >> n;
+
+    cout << fixed << setprecision(7) << 1.0 / 3 << ' ' << n << '\n';
+}
@@ -0,0 +1,14 @@
+#include <fstream>
+#include <iostream>
+
+using namespace std;
+
+int main() {
+    if (ifstream("left.txt")) {
+        cout << "dirty\n";
+        return 0;
+    }
+
+    ofstream("left.txt") << "left by a previous run\n";
+    cout << "clean\n";
+}
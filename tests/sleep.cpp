@@ -0,0 +1,14 @@
+#include <chrono>
+#include <iostream>
+#include <thread>
+
+using namespace std;
+
+int main() {
+    int n;
+    cin >> n;
+
+    this_thread::sleep_for(chrono::seconds(10));
+
+    cout << n << '\n';
+}
@@ -0,0 +1,16 @@
+#include <cassert>
+#include <iostream>
+#include <string>
+
+using namespace std;
+
+int main() {
+    long long n;
+    cin >> n;
+
+    cerr << "Read " << n << '\n';
+    if (n > 10) {
+        cerr << string(100000, 'x');
+    }
+    assert(n < 0);
+}
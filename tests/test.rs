@@ -1,10 +1,43 @@
-use std::process::Command;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::{Child, Command};
 use std::sync::Once;
+use std::thread::sleep;
+use std::time::Duration;
 
-use codechecker::{judge, LinesChecker, ProblemVerdict};
+use codechecker::{
+    base64_decode, base64_encode, judge, judge_directory, judge_directory_with_checker,
+    judge_interactive, judge_with_usage, sorted_list_numbers_in_folder, BuildDir, CgroupConfig,
+    CheckerConfig, ClientError, Compilation, EnvPolicy, ExternalChecker, Interactor, IoMode,
+    JudeRequest, JudgeClient, JudgeEvent, Limits, LinesChecker, Problem, ProblemVerdict, RunConfig,
+    SandboxConfig, Termination, TokensChecker, PROTOCOL_VERSION, STDERR_LIMIT,
+};
 
 static COMPILATION_DONE: Once = Once::new();
-const CPP_FILES: [&str; 4] = ["accepted.cpp", "tle.cpp", "mle.cpp", "wrong_answer.cpp"];
+const CPP_FILES: [&str; 20] = [
+    "accepted.cpp",
+    "tle.cpp",
+    "mle.cpp",
+    "wrong_answer.cpp",
+    "precision.cpp",
+    "checker.cpp",
+    "interactor.cpp",
+    "guess.cpp",
+    "echo.cpp",
+    "sleep.cpp",
+    "stderr.cpp",
+    "exit_code.cpp",
+    "file_io.cpp",
+    "scratch.cpp",
+    "env.cpp",
+    "verbose.cpp",
+    "fork.cpp",
+    "signal.cpp",
+    "abort.cpp",
+    "pid.cpp",
+];
 
 fn compile_cpp_files() {
     COMPILATION_DONE.call_once(|| {
@@ -21,15 +54,46 @@ fn compile_cpp_files() {
     });
 }
 
+/// Judge server started for a test, killed when dropped
+struct Server(Child);
+
+impl Server {
+    fn start(args: &[&str]) -> Self {
+        let child = Command::new(env!("CARGO_BIN_EXE_codechecker"))
+            .args(args)
+            .spawn()
+            .unwrap();
+
+        Self(child)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn limits(time: u64, memory: u64) -> Limits {
+    Limits {
+        time,
+        wall_time: 3 * time,
+        memory,
+        output: 64,
+    }
+}
+
 #[test]
 fn test_wrong_answer() {
     compile_cpp_files();
     let checker = LinesChecker::new("tests/test_cases/1.out");
     let res = judge(
         "tests/wrong_answer.exe",
+        None,
         "tests/test_cases/1.in",
-        1000,
-        128,
+        limits(1000, 128),
+        &RunConfig::default(),
         checker,
     );
 
@@ -43,7 +107,14 @@ fn test_wrong_answer() {
 fn test_mle() {
     compile_cpp_files();
     let checker = LinesChecker::new("tests/test_cases/3.out");
-    let res = judge("tests/mle.exe", "tests/test_cases/3.in", 1000, 128, checker);
+    let res = judge(
+        "tests/mle.exe",
+        None,
+        "tests/test_cases/3.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        checker,
+    );
 
     match res {
         Ok(ProblemVerdict::MemoryLimitExceeded) => {}
@@ -55,12 +126,23 @@ fn test_mle() {
 fn test_tle() {
     compile_cpp_files();
     let checker = LinesChecker::new("tests/test_cases/4.out");
-    let res = judge("tests/tle.exe", "tests/test_cases/4.in", 1000, 128, checker);
+    let res = judge(
+        "tests/tle.exe",
+        None,
+        "tests/test_cases/4.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        checker,
+    );
 
     match res {
         Ok(ProblemVerdict::TimeLimitExceeded) => {}
         _ => panic!("Unexpected result"),
     }
+
+    // The killed program was reaped, it isn't left as a zombie
+    let children = std::fs::read_to_string("/proc/thread-self/children").unwrap_or_default();
+    assert_eq!(children.trim(), "");
 }
 
 #[test]
@@ -69,9 +151,452 @@ fn test_accepted() {
     let checker = LinesChecker::new("tests/test_cases/4.out");
     let res = judge(
         "tests/accepted.exe",
+        None,
+        "tests/test_cases/4.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        checker,
+    );
+
+    match res {
+        Ok(ProblemVerdict::Accepted { .. }) => {}
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn test_tokens_checker_precision() {
+    compile_cpp_files();
+    let res = judge(
+        "tests/precision.exe",
+        None,
+        "tests/test_cases/1.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        TokensChecker::new("tests/checker_cases/precision.out", 1e-6, 1e-6),
+    );
+
+    match res {
+        Ok(ProblemVerdict::Accepted { .. }) => {}
+        _ => panic!("Unexpected result"),
+    }
+
+    let res = judge(
+        "tests/precision.exe",
+        None,
+        "tests/test_cases/1.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        TokensChecker::new("tests/checker_cases/precision.out", 1e-9, 1e-9),
+    );
+
+    match res {
+        Ok(ProblemVerdict::WrongAnswer { .. }) => {}
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn test_external_checker() {
+    compile_cpp_files();
+    let checker = ExternalChecker::new(
+        "tests/checker.exe",
+        "tests/test_cases/2.in",
+        "tests/test_cases/2.out",
+        1000,
+        128,
+    );
+    let res = judge(
+        "tests/accepted.exe",
+        None,
+        "tests/test_cases/2.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        checker,
+    );
+
+    match res {
+        Ok(ProblemVerdict::Accepted { .. }) => {}
+        _ => panic!("Unexpected result"),
+    }
+
+    let checker = ExternalChecker::new(
+        "tests/checker.exe",
+        "tests/test_cases/1.in",
+        "tests/test_cases/1.out",
+        1000,
+        128,
+    );
+    let res = judge(
+        "tests/wrong_answer.exe",
+        None,
+        "tests/test_cases/1.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        checker,
+    );
+
+    match res {
+        Ok(ProblemVerdict::PresentationError { .. }) => {}
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn test_interactive() {
+    compile_cpp_files();
+    let interactor = Interactor::new("tests/interactor.exe", 1000, 128);
+    let res = judge_interactive(
+        "tests/guess.exe",
+        None,
+        "tests/test_cases/4.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        &interactor,
+    );
+
+    match res {
+        Ok(ProblemVerdict::Accepted { .. }) => {}
+        _ => panic!("Unexpected result"),
+    }
+
+    let res = judge_interactive(
+        "tests/wrong_answer.exe",
+        None,
         "tests/test_cases/4.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        &interactor,
+    );
+
+    match res {
+        Ok(ProblemVerdict::WrongAnswer { .. }) => {}
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn test_interaction_blame() {
+    compile_cpp_files();
+    let interactor = Interactor::new("tests/interactor.exe", 1000, 128);
+
+    // The program crashed first, the interactor only found the end of the
+    // interaction
+    for _ in 0..10 {
+        let res = judge_interactive(
+            "tests/abort.exe",
+            None,
+            "tests/test_cases/4.in",
+            limits(1000, 128),
+            &RunConfig::default(),
+            &interactor,
+        );
+
+        match res {
+            Ok(ProblemVerdict::RuntimeError { .. }) => {}
+            _ => panic!("Unexpected result"),
+        }
+    }
+
+    // Both wait for the other until their limits, it's the program's fault
+    let res = judge_interactive(
+        "tests/echo.exe",
+        None,
+        "tests/test_cases/4.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        &interactor,
+    );
+
+    match res {
+        Ok(ProblemVerdict::WallTimeLimitExceeded | ProblemVerdict::IdleLimitExceeded) => {}
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn test_long_checker_messages() {
+    compile_cpp_files();
+    let checker = ExternalChecker::new(
+        "tests/verbose.exe",
+        "tests/test_cases/2.in",
+        "tests/test_cases/2.out",
         1000,
         128,
+    );
+    let res = judge(
+        "tests/accepted.exe",
+        None,
+        "tests/test_cases/2.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        checker,
+    );
+
+    match res {
+        Ok(ProblemVerdict::Accepted { .. }) => {}
+        _ => panic!("Unexpected result"),
+    }
+
+    let interactor = Interactor::new("tests/verbose.exe", 1000, 128);
+    let res = judge_interactive(
+        "tests/accepted.exe",
+        None,
+        "tests/test_cases/2.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        &interactor,
+    );
+
+    match res {
+        Ok(ProblemVerdict::Accepted { .. }) => {}
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn test_large_input_and_output() {
+    compile_cpp_files();
+
+    // Way more than what fits in a pipe buffer
+    let path = std::env::temp_dir().join("codechecker-test-large.txt");
+    let path = path.to_str().unwrap();
+    let content: String = (0..500_000).map(|i| format!("{}\n", i)).collect();
+    std::fs::write(path, content).unwrap();
+
+    let checker = LinesChecker::new(path);
+    let res = judge(
+        "tests/echo.exe",
+        None,
+        path,
+        limits(5000, 128),
+        &RunConfig::default(),
+        checker,
+    );
+
+    std::fs::remove_file(path).unwrap();
+
+    match res {
+        Ok(ProblemVerdict::Accepted { .. }) => {}
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn test_exit_code() {
+    compile_cpp_files();
+    let res = judge(
+        "tests/exit_code.exe",
+        None,
+        "tests/test_cases/1.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        LinesChecker::new("tests/test_cases/1.out"),
+    );
+
+    match res {
+        Ok(ProblemVerdict::RuntimeError {
+            termination: Termination::ExitCode(3),
+            stderr: None,
+        }) => {}
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn test_stderr_capture() {
+    compile_cpp_files();
+    let res = judge(
+        "tests/stderr.exe",
+        None,
+        "tests/test_cases/1.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        LinesChecker::new("tests/test_cases/1.out"),
+    );
+
+    match res {
+        Ok(ProblemVerdict::RuntimeError {
+            termination: Termination::Signal { name, .. },
+            stderr: Some(stderr),
+        }) => {
+            assert_eq!(name, "SIGABRT");
+            assert!(stderr.starts_with("Read 5\n"));
+            assert!(stderr.contains("Assertion"));
+        }
+        _ => panic!("Unexpected result"),
+    }
+
+    // Only the start of a long stderr is kept
+    let (_, _, stderr) = judge_with_usage(
+        "tests/stderr.exe",
+        None,
+        "tests/test_cases/2.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        LinesChecker::new("tests/test_cases/2.out"),
+    )
+    .unwrap();
+    let stderr = stderr.unwrap();
+    assert!(stderr.starts_with("Read 100\nxxx"));
+    assert!(stderr.ends_with("\n[truncated]"));
+    assert_eq!(stderr.len(), STDERR_LIMIT + "\n[truncated]".len());
+}
+
+#[test]
+fn test_file_io() {
+    compile_cpp_files();
+    let config = RunConfig {
+        io: IoMode::Files {
+            input: String::from("input.txt"),
+            output: String::from("output.txt"),
+        },
+        ..RunConfig::default()
+    };
+
+    let res = judge(
+        "tests/file_io.exe",
+        None,
+        "tests/test_cases/2.in",
+        limits(1000, 128),
+        &config,
+        LinesChecker::new("tests/test_cases/2.out"),
+    );
+
+    match res {
+        Ok(ProblemVerdict::Accepted { .. }) => {}
+        _ => panic!("Unexpected result"),
+    }
+
+    // Writing to stdout leaves no output file
+    let res = judge(
+        "tests/accepted.exe",
+        None,
+        "tests/test_cases/2.in",
+        limits(1000, 128),
+        &config,
+        LinesChecker::new("tests/test_cases/2.out"),
+    );
+
+    match res {
+        Ok(ProblemVerdict::PresentationError { msg }) => {
+            assert_eq!(msg, "Output file output.txt not found")
+        }
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn test_scratch_directory() {
+    compile_cpp_files();
+
+    let path = std::env::temp_dir().join("codechecker-test-scratch.txt");
+    let path = path.to_str().unwrap();
+    std::fs::write(path, "clean\n").unwrap();
+
+    // Files left by a run aren't seen by the next one
+    for _ in 0..2 {
+        let res = judge(
+            "tests/scratch.exe",
+            None,
+            "tests/test_cases/1.in",
+            limits(1000, 128),
+            &RunConfig::default(),
+            LinesChecker::new(path),
+        );
+
+        match res {
+            Ok(ProblemVerdict::Accepted { .. }) => {}
+            _ => panic!("Unexpected result"),
+        }
+    }
+
+    std::fs::remove_file(path).unwrap();
+    assert!(!std::path::Path::new("left.txt").exists());
+}
+
+#[test]
+fn test_environment() {
+    compile_cpp_files();
+
+    let judge_env = |env: EnvPolicy, expected: &str| {
+        let path = std::env::temp_dir().join(format!("codechecker-test-env-{}.txt", expected));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, format!("{}\n", expected)).unwrap();
+
+        let config = RunConfig {
+            env,
+            ..RunConfig::default()
+        };
+        let res = judge(
+            "tests/env.exe",
+            None,
+            "tests/test_cases/1.in",
+            limits(1000, 128),
+            &config,
+            LinesChecker::new(path),
+        );
+        std::fs::remove_file(path).unwrap();
+
+        match res {
+            Ok(ProblemVerdict::Accepted { .. }) => {}
+            _ => panic!("Unexpected result"),
+        }
+    };
+
+    // Nothing of the judge is inherited by default
+    judge_env(EnvPolicy::default(), "unset no-path");
+    judge_env(
+        EnvPolicy {
+            pass: vec![String::from("PATH")],
+            set: [(String::from("ONLINE_JUDGE"), String::from("1"))].into(),
+        },
+        "1 path",
+    );
+}
+
+#[test]
+fn test_output_limit() {
+    compile_cpp_files();
+
+    let path = std::env::temp_dir().join("codechecker-test-output-limit.txt");
+    let path = path.to_str().unwrap();
+    let content: String = (0..500_000).map(|i| format!("{}\n", i)).collect();
+    std::fs::write(path, content).unwrap();
+
+    let checker = LinesChecker::new(path);
+    let limits = Limits {
+        output: 1,
+        ..limits(5000, 128)
+    };
+    let res = judge(
+        "tests/echo.exe",
+        None,
+        path,
+        limits,
+        &RunConfig::default(),
+        checker,
+    );
+
+    std::fs::remove_file(path).unwrap();
+
+    match res {
+        Ok(ProblemVerdict::OutputLimitExceeded) => {}
+        _ => panic!("Unexpected result"),
+    }
+
+    // Huge limits are as good as unlimited
+    let checker = LinesChecker::new("tests/test_cases/4.out");
+    let unlimited = Limits {
+        output: u64::MAX,
+        ..limits
+    };
+    let res = judge(
+        "tests/accepted.exe",
+        None,
+        "tests/test_cases/4.in",
+        unlimited,
+        &RunConfig::default(),
         checker,
     );
 
@@ -80,3 +605,833 @@ fn test_accepted() {
         _ => panic!("Unexpected result"),
     }
 }
+
+/// Empty cgroup v2 directory with the memory and pids controllers the test can
+/// create cgroups in, `None` if there's none
+fn cgroup_parent(name: &str) -> Option<PathBuf> {
+    let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+    let root = mounts.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        (fields.get(2) == Some(&"cgroup2")).then(|| PathBuf::from(fields[1]))
+    })?;
+
+    let parent = root.join(format!("codechecker-test-{}-{}", name, std::process::id()));
+    std::fs::create_dir(&parent).ok()?;
+
+    let controllers =
+        std::fs::read_to_string(parent.join("cgroup.controllers")).unwrap_or_default();
+    // memory.peak needs Linux 5.19
+    if !controllers.contains("memory")
+        || !controllers.contains("pids")
+        || !parent.join("memory.peak").exists()
+    {
+        let _ = std::fs::remove_dir(&parent);
+        return None;
+    }
+
+    Some(parent)
+}
+
+#[test]
+fn test_cgroup() {
+    compile_cpp_files();
+
+    // A cgroup that can't be created is an error, not a silent fallback
+    let config = RunConfig {
+        cgroup: Some(CgroupConfig {
+            parent: PathBuf::from("/nonexistent"),
+            max_pids: 8,
+        }),
+        ..RunConfig::default()
+    };
+    let res = judge(
+        "tests/accepted.exe",
+        None,
+        "tests/test_cases/4.in",
+        limits(1000, 128),
+        &config,
+        LinesChecker::new("tests/test_cases/4.out"),
+    );
+    assert!(res.is_err());
+
+    let Some(parent) = cgroup_parent("limits") else {
+        eprintln!(
+            "Skipping test_cgroup, no cgroup v2 directory with the memory and pids controllers"
+        );
+        return;
+    };
+
+    let config = RunConfig {
+        cgroup: Some(CgroupConfig {
+            parent: parent.clone(),
+            max_pids: 8,
+        }),
+        ..RunConfig::default()
+    };
+
+    let mle = judge(
+        "tests/mle.exe",
+        None,
+        "tests/test_cases/3.in",
+        limits(1000, 128),
+        &config,
+        LinesChecker::new("tests/test_cases/3.out"),
+    );
+    let pids = judge(
+        "tests/fork.exe",
+        None,
+        "tests/test_cases/1.in",
+        limits(1000, 128),
+        &config,
+        LinesChecker::new("tests/checker_cases/limited.out"),
+    );
+
+    // The runs removed their own cgroups
+    std::fs::remove_dir(&parent).unwrap();
+
+    match mle {
+        Ok(ProblemVerdict::MemoryLimitExceeded) => {}
+        _ => panic!("Unexpected result"),
+    }
+    match pids {
+        Ok(ProblemVerdict::Accepted { .. }) => {}
+        _ => panic!("Unexpected result"),
+    }
+}
+
+/// Whether unprivileged user namespaces are available for the sandbox
+fn sandbox_available() -> bool {
+    Command::new("unshare")
+        .args(["--user", "--mount", "--net", "true"])
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Sandbox with the host as its root, so the test programs are found at their
+/// absolute paths. The working directory isn't mounted on `/tmp`, which could
+/// hide the tests.
+fn host_sandbox() -> SandboxConfig {
+    SandboxConfig {
+        root: PathBuf::from("/"),
+        mounts: vec![],
+        work_dir: PathBuf::from("/mnt"),
+        build_dir: PathBuf::from("/srv"),
+        filter_syscalls: true,
+    }
+}
+
+#[test]
+fn test_sandbox() {
+    compile_cpp_files();
+    if !sandbox_available() {
+        eprintln!("Skipping test_sandbox, user namespaces are not available");
+        return;
+    }
+
+    let config = RunConfig {
+        sandbox: Some(host_sandbox()),
+        ..RunConfig::default()
+    };
+    let run = |name: &str, answer: &str| {
+        let exe = std::fs::canonicalize(format!("tests/{}.exe", name)).unwrap();
+        judge(
+            exe.to_str().unwrap(),
+            None,
+            "tests/test_cases/4.in",
+            limits(1000, 128),
+            &config,
+            LinesChecker::new(answer),
+        )
+    };
+
+    match run("accepted", "tests/test_cases/4.out") {
+        Ok(ProblemVerdict::Accepted { .. }) => {}
+        _ => panic!("Unexpected result"),
+    }
+    // Signals to itself are allowed
+    match run("stderr", "tests/test_cases/4.out") {
+        Ok(ProblemVerdict::RuntimeError {
+            termination: Termination::Signal { name, .. },
+            ..
+        }) => assert_eq!(name, "SIGABRT"),
+        _ => panic!("Unexpected result"),
+    }
+    match run("pid", "tests/checker_cases/isolated.out") {
+        Ok(ProblemVerdict::Accepted { .. }) => {}
+        _ => panic!("Unexpected result"),
+    }
+    // The usage is read from the program, not the process forwarding its status
+    match run("tle", "tests/test_cases/4.out") {
+        Ok(ProblemVerdict::TimeLimitExceeded) => {}
+        _ => panic!("Unexpected result"),
+    }
+    match run("fork", "tests/checker_cases/limited.out") {
+        Ok(ProblemVerdict::SecurityViolation) => {}
+        _ => panic!("Unexpected result"),
+    }
+    match run("signal", "tests/test_cases/4.out") {
+        Ok(ProblemVerdict::SecurityViolation) => {}
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn test_wall_time_limit() {
+    compile_cpp_files();
+    let checker = LinesChecker::new("tests/test_cases/1.out");
+    let res = judge(
+        "tests/sleep.exe",
+        None,
+        "tests/test_cases/1.in",
+        limits(1000, 128),
+        &RunConfig::default(),
+        checker,
+    );
+
+    match res {
+        Ok(ProblemVerdict::WallTimeLimitExceeded) => {}
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn test_directory_report() {
+    compile_cpp_files();
+    let directory = "tests/test_cases";
+    let test_cases = sorted_list_numbers_in_folder(directory).unwrap();
+
+    let report = judge_directory(
+        "tests/wrong_answer.exe",
+        None,
+        directory,
+        test_cases.clone(),
+        limits(1000, 128),
+        &RunConfig::default(),
+        true,
+    )
+    .unwrap();
+
+    assert_eq!(report.total, 4);
+    assert_eq!(report.passed, 0);
+    assert_eq!(report.failed_test_case, Some(1));
+    assert!(report
+        .tests
+        .iter()
+        .all(|test| matches!(test.verdict, ProblemVerdict::WrongAnswer { .. })));
+
+    let report = judge_directory(
+        "tests/wrong_answer.exe",
+        None,
+        directory,
+        test_cases,
+        limits(1000, 128),
+        &RunConfig::default(),
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(report.total, 1);
+
+    // Each result is given as soon as its test case is judged
+    let mut judged = Vec::new();
+    let report = judge_directory_with_checker(
+        "tests/accepted.exe",
+        None,
+        directory,
+        [1, 2],
+        limits(1000, 128),
+        &RunConfig::default(),
+        &CheckerConfig::Lines,
+        true,
+        |test| judged.push(test.test_case),
+    )
+    .unwrap();
+
+    assert_eq!(judged, [1, 2]);
+    assert_eq!(report.total, 2);
+}
+
+#[test]
+fn test_group_scoring() {
+    compile_cpp_files();
+    let directory = "tests/group_cases";
+    let problem = Problem::load(directory).unwrap().unwrap();
+    let test_cases = problem.test_cases(&sorted_list_numbers_in_folder(directory).unwrap());
+
+    let report = judge_directory(
+        "tests/accepted.exe",
+        None,
+        directory,
+        test_cases,
+        limits(1000, 128),
+        &RunConfig::default(),
+        true,
+    )
+    .unwrap();
+    let score = problem.score(&report);
+
+    let group_scores: Vec<f64> = score.groups.iter().map(|group| group.score).collect();
+    assert_eq!(group_scores, [10.0, 0.0, 40.0, 0.0]);
+    assert!(score.groups[3].dependencies_failed);
+    assert_eq!(score.score, 50.0);
+    assert_eq!(score.max_score, 100.0);
+
+    // Samples worth no points still have to pass
+    let problem: Problem = serde_json::from_str(
+        r#"{
+            "groups": [
+                { "name": "samples", "tests": ["3"], "points": 0 },
+                { "name": "full", "tests": ["1-2"], "points": 100, "depends_on": ["samples"] }
+            ]
+        }"#,
+    )
+    .unwrap();
+    let score = problem.score(&report);
+
+    assert!(score.groups[1].dependencies_failed);
+    assert_eq!(score.score, 0.0);
+}
+
+#[test]
+fn test_invalid_group_spec() {
+    let directory = std::env::temp_dir().join("codechecker-test-invalid-spec");
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(
+        directory.join("problem.json"),
+        r#"{ "groups": [{ "name": "all", "tests": ["abc"], "points": 100 }] }"#,
+    )
+    .unwrap();
+
+    let res = Problem::load(directory.to_str().unwrap());
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_check_json_output() {
+    compile_cpp_files();
+    let output = Command::new(env!("CARGO_BIN_EXE_codechecker"))
+        .args(["check", "tests/accepted.exe", "-d", "tests/group_cases"])
+        .args(["--format", "jsonl"])
+        .output()
+        .unwrap();
+
+    // Wrong answer on the last test case
+    assert_eq!(output.status.code(), Some(10));
+
+    let lines: Vec<serde_json::Value> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0]["test_case"], 1);
+    assert!(lines[2]["verdict"]["WrongAnswer"].is_object());
+    assert_eq!(lines[3]["failed_test_case"], 3);
+    assert_eq!(lines[3]["score"]["score"], 50.0);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_codechecker"))
+        .args(["check", "tests/accepted.exe", "-i", "tests/test_cases/1.in"])
+        .args(["-o", "tests/test_cases/1.out", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(result["verdict"]["Accepted"].is_object());
+    assert!(result["usage"]["cpu_time"].is_u64());
+
+    // Errors are reported in the output format too
+    let output = Command::new(env!("CARGO_BIN_EXE_codechecker"))
+        .args(["check", "tests/accepted.exe", "-d", "tests/missing_cases"])
+        .args(["--format", "json"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(result["error"].is_string());
+}
+
+#[test]
+fn test_judge_client() {
+    compile_cpp_files();
+    let addr = "127.0.0.1:7461";
+    let _server = Server::start(&["listen", addr]);
+
+    let mut client = connect(addr);
+    client.set_timeout(Some(Duration::from_secs(10))).unwrap();
+
+    let request = |test_dir: &str| JudeRequest {
+        version: PROTOCOL_VERSION,
+        cmd: Some(String::from("tests/accepted.exe")),
+        cmd_args: None,
+        source: None,
+        language: None,
+        binary: None,
+        time: Some(1000),
+        wall_time: None,
+        memory: Some(128),
+        output: None,
+        test_dir: Some(String::from(test_dir)),
+        problem: None,
+        run_all: None,
+        env: None,
+    };
+
+    let mut test_cases = Vec::new();
+    let judgment = client
+        .judge(request("tests/group_cases"), |test_case| {
+            test_cases.push(test_case)
+        })
+        .unwrap();
+    assert_eq!(test_cases, [1, 2, 3]);
+    assert!(matches!(
+        judgment.verdict,
+        ProblemVerdict::WrongAnswer { .. }
+    ));
+    assert_eq!(judgment.score.unwrap().score, 50.0);
+
+    // The connection is still usable after an error
+    let res = client.judge(request("tests/missing_cases"), |_| {});
+    assert!(matches!(res, Err(ClientError::Judge(_))));
+
+    let judgment = client.judge(request("tests/test_cases"), |_| {}).unwrap();
+    assert!(matches!(judgment.verdict, ProblemVerdict::Accepted { .. }));
+}
+
+#[test]
+fn test_judge_client_unix() {
+    compile_cpp_files();
+    let path = std::env::temp_dir().join(format!("codechecker-test-{}.sock", std::process::id()));
+    let socket = format!("unix:{}", path.display());
+    let _server = Server::start(&["listen", &socket, "--socket-mode", "600"]);
+
+    let mut client = None;
+    for _ in 0..50 {
+        if let Ok(connected) = JudgeClient::connect_unix(&path) {
+            client = Some(connected);
+            break;
+        }
+        sleep(Duration::from_millis(100));
+    }
+    let mut client = client.expect("Couldn't connect to the judge");
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let request = JudeRequest {
+        version: PROTOCOL_VERSION,
+        cmd: Some(String::from("tests/accepted.exe")),
+        cmd_args: None,
+        source: None,
+        language: None,
+        binary: None,
+        time: Some(1000),
+        wall_time: None,
+        memory: Some(128),
+        output: None,
+        test_dir: Some(String::from("tests/test_cases")),
+        problem: None,
+        run_all: None,
+        env: None,
+    };
+    let judgment = client.judge(request, |_| {}).unwrap();
+    assert!(matches!(judgment.verdict, ProblemVerdict::Accepted { .. }));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+fn http_request(addr: &str, method: &str, path: &str, body: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: {addr}\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_http_api() {
+    compile_cpp_files();
+    let addr = "127.0.0.1:7462";
+    let _server = Server::start(&["http", addr]);
+
+    for _ in 0..50 {
+        if TcpStream::connect(addr).is_ok() {
+            break;
+        }
+        sleep(Duration::from_millis(100));
+    }
+
+    let request = format!(
+        r#"{{"version":{PROTOCOL_VERSION},"cmd":"tests/accepted.exe","time":1000,"memory":128,"test_dir":"tests/group_cases"}}"#
+    );
+    let response = http_request(addr, "POST", "/submissions", &request);
+    assert!(response.starts_with("HTTP/1.1 202"));
+    assert!(response.ends_with(r#"{"id":1}"#));
+
+    let events = http_request(addr, "GET", "/submissions/1/events", "");
+    assert!(events.contains("text/event-stream"));
+    assert!(events.contains("event: test_case\ndata: 3\n\n"));
+    assert!(events.contains("event: finished\ndata: {\"id\":1,\"status\":\"finished\""));
+
+    let tests = http_request(addr, "GET", "/submissions/1/tests", "");
+    assert!(tests.starts_with("HTTP/1.1 200"));
+    assert_eq!(tests.matches("\"test_case\"").count(), 3);
+
+    let missing = http_request(addr, "GET", "/submissions/2", "");
+    assert!(missing.starts_with("HTTP/1.1 404"));
+
+    // A body announced larger than what's sent, or larger than allowed, is
+    // rejected without waiting for it
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "POST /submissions HTTP/1.1\r\nContent-Length: 1000\r\n\r\n{{}}"
+    )
+    .unwrap();
+    stream.shutdown(std::net::Shutdown::Write).unwrap();
+    let mut truncated = String::new();
+    stream.read_to_string(&mut truncated).unwrap();
+    assert!(truncated.starts_with("HTTP/1.1 400"));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "POST /submissions HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+        1u64 << 30
+    )
+    .unwrap();
+    let mut too_large = String::new();
+    stream.read_to_string(&mut too_large).unwrap();
+    assert!(too_large.starts_with("HTTP/1.1 400"));
+}
+
+fn connect(addr: &str) -> JudgeClient {
+    for _ in 0..50 {
+        if let Ok(client) = JudgeClient::connect(addr, Some(Duration::from_secs(1))) {
+            return client;
+        }
+        sleep(Duration::from_millis(100));
+    }
+
+    panic!("Couldn't connect to the judge");
+}
+
+#[test]
+fn test_job_store() {
+    compile_cpp_files();
+    let addr = "127.0.0.1:7463";
+    let store = std::env::temp_dir().join(format!("codechecker-jobs-{}.jsonl", std::process::id()));
+    let store_arg = store.to_str().unwrap();
+    let request = JudeRequest {
+        version: PROTOCOL_VERSION,
+        cmd: Some(String::from("tests/accepted.exe")),
+        cmd_args: None,
+        source: None,
+        language: None,
+        binary: None,
+        time: Some(1000),
+        wall_time: None,
+        memory: Some(128),
+        output: None,
+        test_dir: Some(String::from("tests/test_cases")),
+        problem: None,
+        run_all: None,
+        env: None,
+    };
+
+    let server = Server::start(&["listen", addr, "--jobs", store_arg]);
+
+    // Disconnect as soon as the job is queued
+    let job = {
+        let mut client = connect(addr);
+        let mut events = client.submit(request.clone()).unwrap();
+        match events.next() {
+            Some(Ok(JudgeEvent::Job(job))) => job,
+            event => panic!("Unexpected event {:?}", event),
+        }
+    };
+
+    let mut client = connect(addr);
+    let events: Vec<JudgeEvent> = client.follow(job).unwrap().map(Result::unwrap).collect();
+    assert!(matches!(
+        events.last(),
+        Some(JudgeEvent::Finished(judgment)) if matches!(judgment.verdict, ProblemVerdict::Accepted { .. })
+    ));
+
+    // The result is still there after a restart
+    drop(server);
+    let _server = Server::start(&["listen", addr, "--jobs", store_arg]);
+
+    let mut client = connect(addr);
+    let events: Vec<JudgeEvent> = client.follow(job).unwrap().map(Result::unwrap).collect();
+    assert!(matches!(
+        events.last(),
+        Some(JudgeEvent::Finished(judgment)) if matches!(judgment.verdict, ProblemVerdict::Accepted { .. })
+    ));
+
+    let unknown = client.follow(job + 1).unwrap().next();
+    assert!(matches!(unknown, Some(Err(ClientError::Judge(_)))));
+
+    // A job left queued is judged again from its request file, which is
+    // removed once it's done
+    let requests = store.with_extension("requests");
+    assert_eq!(std::fs::read_dir(&requests).unwrap().count(), 0);
+    drop(_server);
+    let pending = job + 1;
+    let request_file = requests.join(format!("{pending}.json"));
+    std::fs::write(&request_file, serde_json::to_string(&request).unwrap()).unwrap();
+    let mut content = std::fs::read_to_string(&store).unwrap();
+    content.push_str(&format!(
+        "{{\"job\":{{\"id\":{pending},\"status\":\"queued\",\"test_cases\":[]}},\"request\":\"{}\"}}\n",
+        request_file.strip_prefix(store.parent().unwrap()).unwrap().display()
+    ));
+    std::fs::write(&store, content).unwrap();
+    let _server = Server::start(&["listen", addr, "--jobs", store_arg]);
+
+    let mut client = connect(addr);
+    let events: Vec<JudgeEvent> = client
+        .follow(pending)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert!(matches!(
+        events.last(),
+        Some(JudgeEvent::Finished(judgment)) if matches!(judgment.verdict, ProblemVerdict::Accepted { .. })
+    ));
+    assert!(!request_file.exists());
+
+    std::fs::remove_file(store).unwrap();
+    std::fs::remove_dir(requests).unwrap();
+}
+
+#[test]
+fn test_job_retention() {
+    compile_cpp_files();
+    let addr = "127.0.0.1:7465";
+    let store = std::env::temp_dir().join(format!(
+        "codechecker-retention-{}.jsonl",
+        std::process::id()
+    ));
+    let store_arg = store.to_str().unwrap();
+    let request = || JudeRequest {
+        version: PROTOCOL_VERSION,
+        cmd: Some(String::from("tests/accepted.exe")),
+        cmd_args: None,
+        source: None,
+        language: None,
+        binary: None,
+        time: Some(1000),
+        wall_time: None,
+        memory: Some(128),
+        output: None,
+        test_dir: Some(String::from("tests/test_cases")),
+        problem: None,
+        run_all: None,
+        env: None,
+    };
+
+    let _server = Server::start(&["listen", addr, "--jobs", store_arg, "--keep-jobs", "1"]);
+
+    let mut jobs = Vec::new();
+    for _ in 0..2 {
+        let mut client = connect(addr);
+        let events: Vec<JudgeEvent> = client
+            .submit(request())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        match (events.first(), events.last()) {
+            (Some(JudgeEvent::Job(job)), Some(JudgeEvent::Finished(_))) => jobs.push(*job),
+            _ => panic!("Unexpected events {:?}", events),
+        }
+    }
+
+    // Only the last job is kept, and the store no longer has its request
+    let mut client = connect(addr);
+    let forgotten = client.follow(jobs[0]).unwrap().next();
+    assert!(matches!(forgotten, Some(Err(ClientError::Judge(_)))));
+    let kept = client.follow(jobs[1]).unwrap().last();
+    assert!(matches!(kept, Some(Ok(JudgeEvent::Finished(_)))));
+
+    let content = std::fs::read_to_string(&store).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("\"request\":null"));
+
+    std::fs::remove_file(&store).unwrap();
+    std::fs::remove_dir(store.with_extension("requests")).unwrap();
+}
+
+#[test]
+fn test_compilation() {
+    let config = RunConfig::default();
+    let profile = config.language("cpp").unwrap();
+    let build_dir = BuildDir::new().unwrap();
+
+    let source = std::fs::read_to_string("tests/accepted.cpp").unwrap();
+    let program = match profile.compile(&source, build_dir.path(), &config) {
+        Ok(Compilation::Success(program)) => program,
+        res => panic!("Unexpected result {:?}", res),
+    };
+
+    let res = judge(
+        &program.cmd,
+        Some(program.args),
+        "tests/test_cases/1.in",
+        limits(1000, 128),
+        &config,
+        LinesChecker::new("tests/test_cases/1.out"),
+    );
+    assert!(matches!(res, Ok(ProblemVerdict::Accepted { .. })));
+
+    let res = profile.compile("int main() { return x; }", build_dir.path(), &config);
+    match res {
+        Ok(Compilation::Error(log)) => assert!(log.contains("'x' was not declared")),
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn test_problem_submission() {
+    compile_cpp_files();
+    let addr = "127.0.0.1:7464";
+    let _server = Server::start(&["listen", addr, "--problems", "tests/problems"]);
+
+    let binary = std::fs::read("tests/precision.exe").unwrap();
+    let encoded = base64_encode(&binary);
+    assert_eq!(base64_decode(&encoded).unwrap(), binary);
+    assert_eq!(base64_encode(b"Man"), "TWFu");
+    assert_eq!(base64_encode(b"Ma"), "TWE=");
+    assert_eq!(base64_decode("TQ==").unwrap(), b"M");
+    assert_eq!(base64_decode("TW\nFu").unwrap(), b"Man");
+    assert!(base64_decode("TQ").is_err());
+    assert!(base64_decode("TQ==TWFu").is_err());
+    assert!(base64_decode("T===").is_err());
+
+    let request = |binary: Option<String>, cmd: Option<String>| JudeRequest {
+        version: PROTOCOL_VERSION,
+        cmd,
+        cmd_args: None,
+        source: None,
+        language: None,
+        binary,
+        // The limits come from the catalog
+        time: None,
+        wall_time: None,
+        memory: None,
+        output: None,
+        test_dir: None,
+        problem: Some(String::from("precision")),
+        run_all: None,
+        env: None,
+    };
+
+    let mut client = connect(addr);
+    let problems = client.list_problems().unwrap();
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].id, "precision");
+    assert_eq!(
+        (problems[0].limits.time, problems[0].limits.memory),
+        (1000, 128)
+    );
+    assert_eq!(problems[0].test_cases, 1);
+
+    // Only accepted with the tokens checker of the problem
+    let judgment = client
+        .judge(request(Some(encoded.clone()), None), |_| {})
+        .unwrap();
+    assert!(matches!(judgment.verdict, ProblemVerdict::Accepted { .. }));
+
+    let res = client.judge(
+        JudeRequest {
+            problem: Some(String::from("../test_cases")),
+            ..request(Some(encoded), None)
+        },
+        |_| {},
+    );
+    assert!(matches!(res, Err(ClientError::Judge(_))));
+
+    // Paths on the judge are rejected
+    let res = client.judge(
+        request(None, Some(String::from("tests/accepted.exe"))),
+        |_| {},
+    );
+    assert!(matches!(res, Err(ClientError::Judge(_))));
+}
+
+#[test]
+fn test_sandboxed_submission() {
+    compile_cpp_files();
+    if !sandbox_available() {
+        eprintln!("Skipping test_sandboxed_submission, user namespaces are not available");
+        return;
+    }
+
+    let addr = "127.0.0.1:7466";
+    let _server = Server::start(&[
+        "listen",
+        addr,
+        "--problems",
+        "tests/problems",
+        "--sandbox",
+        "/",
+        "--sandbox-work-dir",
+        "/mnt",
+        "--sandbox-build-dir",
+        "/srv",
+    ]);
+
+    let request = JudeRequest {
+        version: PROTOCOL_VERSION,
+        cmd: None,
+        cmd_args: None,
+        source: None,
+        language: None,
+        binary: None,
+        time: None,
+        wall_time: None,
+        memory: None,
+        output: None,
+        test_dir: None,
+        problem: Some(String::from("precision")),
+        run_all: None,
+        env: None,
+    };
+
+    // The uploaded program is run from the build directory in the sandbox
+    let binary = std::fs::read("tests/precision.exe").unwrap();
+    let mut client = connect(addr);
+    let judgment = client
+        .judge(
+            JudeRequest {
+                binary: Some(base64_encode(&binary)),
+                ..request.clone()
+            },
+            |_| {},
+        )
+        .unwrap();
+    assert!(matches!(judgment.verdict, ProblemVerdict::Accepted { .. }));
+
+    // And so is the compiled one, built in the sandbox
+    let source = std::fs::read_to_string("tests/precision.cpp").unwrap();
+    let judgment = client
+        .judge(
+            JudeRequest {
+                source: Some(source),
+                language: Some(String::from("cpp")),
+                ..request
+            },
+            |_| {},
+        )
+        .unwrap();
+    assert!(matches!(judgment.verdict, ProblemVerdict::Accepted { .. }));
+}
@@ -0,0 +1,22 @@
+#include <iostream>
+#include <string>
+#include <unistd.h>
+
+using namespace std;
+
+// Checker or interactor accepting everything after writing a message longer
+// than a pipe can hold
+int main(int argc, char *argv[]) {
+    string line(1023, '.');
+    for (int i = 0; i < 1024; i++) cerr << line << '\n';
+
+    // As an interactor, send nothing and wait for the program to finish
+    if (argc == 3) {
+        close(STDOUT_FILENO);
+
+        string token;
+        while (cin >> token) {}
+    }
+
+    return 0;
+}